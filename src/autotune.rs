@@ -0,0 +1,91 @@
+use std::{
+    process::Stdio,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::Cmd;
+
+/// The tasks each worker runs at a concurrency level.
+const TASKS_PER_WORKER: usize = 2;
+
+/// The throughput measured at one concurrency level.
+pub struct Level {
+    pub workers: usize,
+    pub tasks: usize,
+    pub elapsed: Duration,
+}
+
+impl Level {
+    pub fn throughput(&self) -> f64 {
+        self.tasks as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// Runs samples of the tasks at doubling concurrency levels, up to twice
+/// the number of cores, see `--autotune`.
+///
+/// Every level runs `TASKS_PER_WORKER` tasks per worker, taken from the
+/// start of the plan and repeated if there are fewer. Dependencies and
+/// barriers are ignored and the output is discarded, so the probes
+/// should be free of side effects. Probing stops before a level that
+/// would exceed the budget, judging by the duration of the level before.
+pub fn probe(tasks: &[&Cmd], budget: Duration) -> Vec<Level> {
+    let max = (num_cpus::get() * 2).max(1);
+    let started = Instant::now();
+    let mut levels: Vec<Level> = Vec::new();
+    let mut workers = 1;
+    while workers <= max && !tasks.is_empty() {
+        if let Some(last) = levels.last() {
+            // Twice the tasks on twice the workers take about as long
+            if started.elapsed() + last.elapsed > budget {
+                break;
+            }
+        }
+        levels.push(run_level(tasks, workers));
+        if workers == max {
+            break;
+        }
+        workers = (workers * 2).min(max);
+    }
+    levels
+}
+
+fn run_level(tasks: &[&Cmd], workers: usize) -> Level {
+    let count = workers * TASKS_PER_WORKER;
+    let queue = Mutex::new(tasks.iter().cycle().take(count));
+    let t0 = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let Some(cmd) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let mut command = cmd.command();
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                if let Ok(mut child) = command.spawn() {
+                    let _ = child.wait();
+                }
+            });
+        }
+    });
+    Level {
+        workers,
+        tasks: count,
+        elapsed: t0.elapsed(),
+    }
+}
+
+/// The recommended worker count: the fewest workers that come within 5%
+/// of the best throughput, since more workers than that mostly add
+/// contention for the same result.
+pub fn recommend(levels: &[Level]) -> Option<usize> {
+    let best = levels.iter().map(Level::throughput).fold(0.0, f64::max);
+    levels
+        .iter()
+        .find(|level| level.throughput() >= best * 0.95)
+        .map(|level| level.workers)
+}
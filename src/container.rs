@@ -0,0 +1,54 @@
+use std::path::Path;
+
+/// The container a task runs in, see `--container`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// The container runtime, which must understand the flags of
+    /// `docker run`, like `docker` or `podman`.
+    pub runtime: String,
+    pub image: String,
+}
+
+impl Container {
+    /// The arguments of the runtime to run a program in the container:
+    ///
+    /// ```text
+    /// run --rm -v <cwd>:<cwd> -w <cwd> [--cpuset-cpus <cpus>] [-e KEY=VALUE]... <image> <program> <args>...
+    /// ```
+    ///
+    /// The working directory is mounted at the same path, so relative
+    /// paths in the arguments resolve like outside of the container. The
+    /// variables of the task are passed with `-e`, everything else comes
+    /// from the image.
+    pub fn args(
+        &self,
+        program: &str,
+        args: &[String],
+        env: &[(String, String)],
+        cwd: &Path,
+        cpus: Option<&[usize]>,
+    ) -> Vec<String> {
+        let cwd = cwd.to_string_lossy();
+        let mut wrapped = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "-v".to_string(),
+            format!("{}:{}", cwd, cwd),
+            "-w".to_string(),
+            cwd.to_string(),
+        ];
+        if let Some(cpus) = cpus {
+            let cpus = cpus.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            wrapped.push("--cpuset-cpus".to_string());
+            wrapped.push(cpus.join(","));
+        }
+        for (key, value) in env {
+            wrapped.push("-e".to_string());
+            wrapped.push(format!("{}={}", key, value));
+        }
+        wrapped.push(self.image.clone());
+        wrapped.push(program.to_string());
+        wrapped.extend_from_slice(args);
+        wrapped
+    }
+}
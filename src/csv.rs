@@ -0,0 +1,142 @@
+/// Parses a CSV document (RFC 4180) into its records.
+///
+/// Fields may be quoted with `"`, in which case they can contain commas,
+/// line breaks and doubled quotes (`""`). Records are separated by `\n`
+/// or `\r\n`, and a trailing line break does not start a new record.
+pub fn parse(s: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => {
+                let start = line;
+                loop {
+                    match chars.next() {
+                        None => {
+                            return Err(format!("unterminated quote starting on line {}", start))
+                        }
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                    }
+                }
+                match chars.peek() {
+                    None | Some(',' | '\n' | '\r') => {}
+                    Some(_) => {
+                        return Err(format!("unexpected character after quote on line {}", line))
+                    }
+                }
+            }
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+                line += 1;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Selects the values of a column, which is identified by the header in
+/// the first record. Every record must have as many fields as the header.
+pub fn column(records: &[Vec<String>], name: &str) -> Result<Vec<String>, String> {
+    let (header, rows) = records.split_first().ok_or("the file is empty")?;
+    let idx = header
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| format!("no column '{}'", name))?;
+
+    rows.iter()
+        .enumerate()
+        .map(|(i, row)| {
+            if row.len() != header.len() {
+                return Err(format!(
+                    "row {} has {} fields, but the header has {}",
+                    i + 1,
+                    row.len(),
+                    header.len()
+                ));
+            }
+            Ok(row[idx].clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(records: &[&[&str]]) -> Vec<Vec<String>> {
+        records
+            .iter()
+            .map(|r| r.iter().map(|f| f.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parses_records() {
+        assert_eq!(
+            parse("a,b\n1,2\r\n3,\n").unwrap(),
+            strings(&[&["a", "b"], &["1", "2"], &["3", ""]])
+        );
+        assert_eq!(parse("a,b").unwrap(), strings(&[&["a", "b"]]));
+        assert_eq!(parse("").unwrap(), strings(&[]));
+    }
+
+    #[test]
+    fn parses_quoted_fields() {
+        assert_eq!(
+            parse("\"a,b\",\"line\nbreak\",\"say \"\"hi\"\"\"\r\n\"\",x\n").unwrap(),
+            strings(&[&["a,b", "line\nbreak", "say \"hi\""], &["", "x"]])
+        );
+        // Quotes only delimit fields that start with one
+        assert_eq!(parse("a\"b,c\n").unwrap(), strings(&[&["a\"b", "c"]]));
+        assert_eq!(parse("é,中\n").unwrap(), strings(&[&["é", "中"]]));
+    }
+
+    #[test]
+    fn rejects_malformed_quotes() {
+        assert_eq!(
+            parse("a\n\"b\nc"),
+            Err("unterminated quote starting on line 2".to_string())
+        );
+        assert_eq!(
+            parse("\"a\"b,c"),
+            Err("unexpected character after quote on line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn selects_columns() {
+        let records = parse("x,y\n1,2\n3,4\n").unwrap();
+        assert_eq!(column(&records, "y").unwrap(), vec!["2", "4"]);
+        assert_eq!(column(&records, "z"), Err("no column 'z'".to_string()));
+        assert_eq!(column(&[], "x"), Err("the file is empty".to_string()));
+
+        let ragged = parse("x,y\n1\n").unwrap();
+        assert_eq!(
+            column(&ragged, "x"),
+            Err("row 1 has 1 fields, but the header has 2".to_string())
+        );
+    }
+}
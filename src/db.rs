@@ -0,0 +1,177 @@
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::TaskResult;
+
+/// The schema of the results database. Every completed task is one row,
+/// and rows of different invocations are told apart by `run_id`. The
+/// captured metrics of a task are stored as one row each in `metrics`,
+/// so arbitrary metric names need no schema changes.
+///
+/// Both statements are idempotent, so an existing database is reused and
+/// extended across runs. Columns added since, like `repeat`, are added to
+/// the tables of an existing database by `MIGRATIONS`. The indices cover
+/// the usual queries across sweeps: all rows of one run, and one task over
+/// all runs. Further indices (e.g. on `timestamp`) can be added to the
+/// database manually, prun never drops or alters them.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS results (
+    run_id TEXT NOT NULL,
+    task_name TEXT NOT NULL,
+    args TEXT NOT NULL,
+    dims TEXT NOT NULL,
+    duration_ms REAL NOT NULL,
+    exit_code INTEGER,
+    metric TEXT,
+    timestamp INTEGER NOT NULL,
+    repeat INTEGER
+);
+CREATE INDEX IF NOT EXISTS results_run_id ON results (run_id);
+CREATE INDEX IF NOT EXISTS results_task_name ON results (task_name);
+CREATE TABLE IF NOT EXISTS metrics (
+    run_id TEXT NOT NULL,
+    task_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value
+);
+CREATE INDEX IF NOT EXISTS metrics_run_id ON metrics (run_id, task_name);
+";
+
+/// The columns added to `results` after its first release, with their
+/// type. They are appended to the tables of existing databases that
+/// lack them, so rows keep their column order.
+const MIGRATIONS: &[(&str, &str)] = &[("repeat", "INTEGER")];
+
+/// A SQLite results database.
+///
+/// The statements are streamed into a `sqlite3` shell process, which must
+/// be available on the PATH. Rows are inserted in batches, each batch
+/// being a single transaction.
+pub struct Db {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    run_id: String,
+}
+
+impl Db {
+    /// Creates the schema before starting the process that the rows are
+    /// streamed into, so that a database which cannot be opened fails
+    /// here rather than once the results are written.
+    pub fn open(path: &Path, run_id: String) -> std::io::Result<Db> {
+        execute(path, SCHEMA)?;
+        let columns = execute(path, "SELECT name FROM pragma_table_info('results');")?;
+        for (column, kind) in MIGRATIONS {
+            if !columns.lines().any(|name| name == *column) {
+                execute(
+                    path,
+                    &format!("ALTER TABLE results ADD COLUMN {} {};", column, kind),
+                )?;
+            }
+        }
+
+        let mut child = Command::new("sqlite3")
+            .arg("-bail")
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().unwrap());
+
+        Ok(Db {
+            child,
+            stdin,
+            run_id,
+        })
+    }
+
+    pub fn insert(&mut self, batch: &[TaskResult]) -> std::io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.stdin, "BEGIN;")?;
+        for result in batch {
+            writeln!(
+                self.stdin,
+                "INSERT INTO results VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {});",
+                quote(&self.run_id),
+                quote(&result.name),
+                quote(&result.args_json().to_string()),
+                quote(&result.dims_json().to_string()),
+                result.duration.as_secs_f64() * 1000.0,
+                result
+                    .exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "NULL".to_string()),
+                result
+                    .metric
+                    .as_deref()
+                    .map(quote)
+                    .unwrap_or_else(|| "NULL".to_string()),
+                result.timestamp,
+                result
+                    .repeat
+                    .map(|r| r.to_string())
+                    .unwrap_or_else(|| "NULL".to_string()),
+            )?;
+
+            for (name, value) in &result.metrics {
+                // Numeric values are stored as numbers, so they can be aggregated
+                let value = match value.parse::<f64>() {
+                    Ok(v) if v.is_finite() => v.to_string(),
+                    _ => quote(value),
+                };
+                writeln!(
+                    self.stdin,
+                    "INSERT INTO metrics VALUES ({}, {}, {}, {});",
+                    quote(&self.run_id),
+                    quote(&result.name),
+                    quote(name),
+                    value,
+                )?;
+            }
+        }
+        writeln!(self.stdin, "COMMIT;")?;
+        self.stdin.flush()
+    }
+
+    pub fn close(self) -> std::io::Result<()> {
+        let Db {
+            mut child, stdin, ..
+        } = self;
+
+        drop(stdin.into_inner().map_err(|e| e.into_error())?);
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "sqlite3 exited with {}",
+                status
+            )))
+        }
+    }
+}
+
+/// Runs statements on the database, returning their output.
+fn execute(path: &Path, sql: &str) -> std::io::Result<String> {
+    let output = Command::new("sqlite3")
+        .arg("-bail")
+        .arg(path)
+        .arg(sql)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use crate::json::Json;
+
+/// A system log that receives the per-task records, see `LogTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogKind {
+    Syslog,
+    Journald,
+}
+
+impl FromStr for LogKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syslog" => Ok(LogKind::Syslog),
+            "journald" => Ok(LogKind::Journald),
+            _ => Err(format!(
+                "unknown output target '{}', expected 'syslog' or 'journald'",
+                s
+            )),
+        }
+    }
+}
+
+/// An open system log.
+///
+/// Records are the JSON records of `--format json`. Syslog receives them
+/// as the message text. The journal receives every field of a record as
+/// a journal field of the same name in upper case, prefixed with `PRUN_`
+/// (e.g. `PRUN_EXIT_CODE`), while the message is the text record.
+pub enum LogTarget {
+    #[cfg(unix)]
+    Syslog,
+    #[cfg(target_os = "linux")]
+    Journald(std::os::unix::net::UnixDatagram),
+}
+
+#[cfg(target_os = "linux")]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+impl LogTarget {
+    pub fn open(kind: LogKind) -> std::io::Result<LogTarget> {
+        match kind {
+            #[cfg(unix)]
+            LogKind::Syslog => {
+                // SAFETY: the identifier is a static C string, which stays valid
+                unsafe { libc::openlog(c"prun".as_ptr(), libc::LOG_PID, libc::LOG_USER) };
+                Ok(LogTarget::Syslog)
+            }
+            #[cfg(target_os = "linux")]
+            LogKind::Journald => {
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(JOURNAL_SOCKET)?;
+                Ok(LogTarget::Journald(socket))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{:?} is not available on this platform", kind),
+            )),
+        }
+    }
+
+    pub fn send(&self, message: &str, record: &Json) -> std::io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            LogTarget::Syslog => {
+                let text = std::ffi::CString::new(record.to_string().replace('\0', ""))
+                    .expect("nul bytes were removed");
+                // SAFETY: both the format and the argument are valid C strings
+                unsafe { libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), text.as_ptr()) };
+                Ok(())
+            }
+            #[cfg(target_os = "linux")]
+            LogTarget::Journald(socket) => {
+                let mut entry = Vec::new();
+                journal_field(&mut entry, "MESSAGE", message);
+                journal_field(&mut entry, "PRIORITY", "6");
+                journal_field(&mut entry, "SYSLOG_IDENTIFIER", "prun");
+                for (key, value) in record.as_object().unwrap_or_default() {
+                    let value = match value {
+                        Json::Null => continue,
+                        Json::Str(s) => s.clone(),
+                        value => value.to_string(),
+                    };
+                    let key = format!("PRUN_{}", key.to_ascii_uppercase());
+                    journal_field(&mut entry, &key, &value);
+                }
+                socket.send(&entry).map(|_| ())
+            }
+        }
+    }
+}
+
+/// Appends a field in the native journal protocol. Values with line
+/// breaks need the binary form, which is prefixed with their length.
+#[cfg(target_os = "linux")]
+fn journal_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
@@ -1,24 +1,252 @@
+use container::Container;
+use db::Db;
+use json::Json;
+use log::{LogKind, LogTarget};
+use prometheus::Textfile;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sink::ResultSink;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    ffi::OsStr,
     fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Write},
-    path::PathBuf,
-    process::{Command, Stdio},
-    sync::{atomic::*, Arc, Mutex},
-    time::Instant,
+    io::{BufReader, BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::{atomic::*, Arc, Condvar, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use structopt::StructOpt;
+use trace::Trace;
+use tui::Board;
+
+mod autotune;
+mod container;
+mod csv;
+mod db;
+mod json;
+mod log;
+mod prometheus;
+mod regex;
+mod sink;
+mod trace;
+mod tui;
+
+/// Whether the informational output goes to stderr, see `--output-on-stdout`.
+static INFO_ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Whether the informational output is dropped, while `--tui` shows the
+/// run instead.
+static INFO_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Like `print!`, but for informational output, which moves to stderr if
+/// the records are written to stdout. Stdout is flushed right away.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if INFO_HIDDEN.load(Ordering::Relaxed) {
+        } else if INFO_ON_STDERR.load(Ordering::Relaxed) {
+            eprint!($($arg)*);
+        } else {
+            print!($($arg)*);
+            let _ = std::io::stdout().flush();
+        }
+    };
+}
+
+/// Like `println!`, see `info!`.
+macro_rules! infoln {
+    ($($arg:tt)*) => {
+        if INFO_HIDDEN.load(Ordering::Relaxed) {
+        } else if INFO_ON_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// The command line. Without a subcommand, `run` is assumed, so that
+/// `prun <config>` keeps working.
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "prun",
+    about = "Runs the combinations of arguments of tasks in parallel"
+)]
+#[allow(clippy::large_enum_variant)]
+enum Cli {
+    #[structopt(about = "Runs the tasks of a config (the default)")]
+    Run(Opt),
+    #[structopt(about = "Lists the names of the selected tasks")]
+    List(Selection),
+    #[structopt(about = "Checks the config and that the program of every task exists")]
+    Check(Selection),
+    #[structopt(about = "Prints the selected tasks as a JSON plan")]
+    Plan(PlanOpt),
+}
+
+/// Where the tasks come from and which of them are selected, shared by
+/// all subcommands.
+#[derive(StructOpt, Debug)]
+struct Selection {
+    #[structopt(
+        name = "file",
+        parse(from_os_str),
+        required_unless = "replay",
+        help = "Specifies the config file"
+    )]
+    config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "A shell command that must succeed before `prun run` runs any task, \
+                skipped with --simulate and by the other subcommands"
+    )]
+    preflight: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Starts tasks and the preflight with an empty environment, except for the variables from --env-keep"
+    )]
+    clean_env: bool,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        number_of_values = 1,
+        help = "The parent environment variables passed through with --clean-env, PATH and HOME unless given. \
+                Tasks in containers only get the variables given here"
+    )]
+    env_keep: Vec<String>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_max_name_length),
+        help = "Truncates task names to n characters (at least 8), keeping them unique with a hash suffix"
+    )]
+    max_name_length: Option<usize>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Runs the tasks of a JSON plan verbatim instead of a config"
+    )]
+    replay: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        number_of_values = 1,
+        help = "Only runs tasks whose name contains one of the given patterns [env: PRUN_FILTER]"
+    )]
+    filter: Vec<String>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        number_of_values = 1,
+        help = "Only runs tasks with one of the given tags [env: PRUN_ONLY_TAG]"
+    )]
+    only_tag: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Alternates between the top-level tasks instead of running them one after another"
+    )]
+    fair: bool,
+
+    #[structopt(
+        long,
+        help = "Skips tasks whose output file is newer than their input file"
+    )]
+    incremental: bool,
+
+    #[structopt(
+        long,
+        help = "Skips the first n tasks, in the order of expansion like ${index}, and runs the rest. \
+                Applies before --filter and --random-assignment"
+    )]
+    continue_from: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Runs the tasks in a random order, so that neither workers nor the time of the run correlate with the tasks"
+    )]
+    random_assignment: bool,
+
+    #[structopt(
+        long,
+        requires = "random-assignment",
+        help = "The seed of --random-assignment, printed on every run so it can be repeated"
+    )]
+    seed: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Formats float values in arguments and names with exactly n decimals, also whole ones, \
+                whether from a range, a list or a matrix. By default they are rounded to 6 decimals, \
+                without trailing zeros"
+    )]
+    float_precision: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Runs every task in a container of this image, unless the task sets its own container. \
+                Runs `<runtime> run --rm -v <cwd>:<cwd> -w <cwd> -e KEY=VALUE... <image> <command> <args>...`"
+    )]
+    container: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "docker",
+        help = "The container runtime of --container, e.g. 'podman'"
+    )]
+    container_runtime: String,
+}
+
+impl Selection {
+    fn env_policy(&self) -> EnvPolicy {
+        EnvPolicy {
+            clean: self.clean_env,
+            keep: self.env_keep.clone(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+struct PlanOpt {
+    #[structopt(flatten)]
+    selection: Selection,
+
+    #[structopt(
+        long,
+        conflicts_with = "summary",
+        help = "Prints how each task was derived from the config instead"
+    )]
+    explain: bool,
+
+    #[structopt(
+        long,
+        help = "Prints the number of tasks per value of each dimension instead"
+    )]
+    summary: bool,
+}
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "prun")]
 struct Opt {
-    #[structopt(name = "file", parse(from_os_str), help = "Specifies the config file")]
-    config: PathBuf,
+    #[structopt(flatten)]
+    selection: Selection,
 
     #[structopt(short, long, help = "Prints debug information while running")]
     verbose: bool,
 
+    #[structopt(
+        long,
+        conflicts_with = "verbose",
+        help = "Prints nothing but failed tasks while running, instead of the progress"
+    )]
+    quiet_success: bool,
+
     #[structopt(
         short,
         long,
@@ -26,6 +254,27 @@ struct Opt {
     )]
     num_threads: Option<usize>,
 
+    #[structopt(
+        long,
+        conflicts_with_all = &["num-threads", "simulate"],
+        help = "Measures the throughput of samples of the tasks at several worker counts, then runs with the best one"
+    )]
+    autotune: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["num-threads", "simulate"],
+        help = "Like --autotune, but only prints the measurements and the recommended --num-threads"
+    )]
+    autotune_only: bool,
+
+    #[structopt(
+        long,
+        default_value = "30",
+        help = "The seconds --autotune may spend on measuring"
+    )]
+    autotune_budget: f64,
+
     #[structopt(
         short,
         long,
@@ -35,296 +284,5802 @@ struct Opt {
 
     #[structopt(short, long, parse(from_os_str), help = "Specifies the output file")]
     output: Option<PathBuf>,
-}
 
-type Tasks = HashMap<String, Task>;
+    #[structopt(
+        long,
+        conflicts_with_all = &["output", "output-dir"],
+        help = "Writes the records to stdout as newline-delimited JSON (requires --format json), \
+                and everything else to stderr"
+    )]
+    output_on_stdout: bool,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Task {
-    id: Option<usize>,
-    command: String,
-    args: Vec<Argument>,
-}
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "output",
+        help = "Specifies a directory to write the output into"
+    )]
+    output_dir: Option<PathBuf>,
 
-impl Task {
-    fn to_concreate_tasks(&self, name: &str) -> Vec<Cmd> {
-        let mut res = Vec::new();
+    #[structopt(
+        long,
+        requires = "output-dir",
+        help = "Writes one output file per top-level task into the output directory"
+    )]
+    split_output_by_task: bool,
 
-        fn p(
-            args: &[Argument],
-            mut so_far: Vec<String>,
-            mut so_far_name: String,
-            res: &mut Vec<(Vec<String>, String)>,
-        ) {
-            if args.is_empty() {
-                res.push((so_far, so_far_name));
-                return;
-            }
+    #[structopt(
+        long,
+        help = "Pauses after every n tasks until Enter is pressed (interactive sessions only)"
+    )]
+    step: Option<usize>,
 
-            match &args[0] {
-                Argument::Static(str) => {
-                    so_far.push(str.clone());
-                    so_far_name.push(',');
-                    so_far_name.push_str(str);
-                    p(&args[1..], so_far, so_far_name, res)
-                }
-                Argument::Choice(opts) => {
-                    for opt in opts {
-                        let mut sf = so_far.clone();
-                        sf.push(opt.clone());
+    #[structopt(
+        long,
+        help = "Runs tasks even if the file of their skip_if_exists exists"
+    )]
+    force: bool,
 
-                        let mut sfn = so_far_name.clone();
-                        sfn.push(',');
-                        sfn.push_str(opt);
+    #[structopt(
+        long,
+        conflicts_with_all = &["step", "output-on-stdout"],
+        help = "Shows the tasks in a terminal UI, which can be sorted and filtered and stays up after the run"
+    )]
+    tui: bool,
 
-                        p(&args[1..], sf, sfn, res)
-                    }
-                }
-                Argument::Range(range) => match range {
-                    RangeObject::Int {
-                        from,
-                        to,
-                        step,
-                        prefix,
-                    } => {
-                        let mut c = *from;
-                        while c <= *to {
-                            let mut sf = so_far.clone();
-                            sf.push(format!(
-                                "{}{}",
-                                prefix.as_ref().unwrap_or(&String::new()),
-                                c
-                            ));
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Records the results in a SQLite database (requires sqlite3)"
+    )]
+    db: Option<PathBuf>,
 
-                            let mut sfn = so_far_name.clone();
-                            sfn.push(',');
-                            sfn.push_str(&format!("{}", c));
+    /// Deprecated, `prun plan` prints the plan.
+    #[structopt(long, hidden = true)]
+    print_plan_json: bool,
 
-                            p(&args[1..], sf, sfn, res);
-                            c += *step;
-                        }
-                    }
-                    RangeObject::Float {
-                        from,
-                        to,
-                        step,
-                        prefix,
-                    } => {
-                        let mut c = *from;
-                        while c <= *to {
-                            let mut sf = so_far.clone();
-                            sf.push(format!(
-                                "{}{}",
-                                prefix.as_ref().unwrap_or(&String::new()),
-                                c
-                            ));
+    #[structopt(
+        long,
+        help = "Disables the warning about more CPU-bound workers than cores"
+    )]
+    no_oversubscription_warning: bool,
 
-                            let mut sfn = so_far_name.clone();
-                            sfn.push(',');
-                            sfn.push_str(&format!("{}", c));
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Writes a Chrome trace (chrome://tracing, Perfetto) of the run"
+    )]
+    trace: Option<PathBuf>,
 
-                            p(&args[1..], sf, sfn, res);
-                            c += *step;
-                        }
-                    }
-                },
-            }
-        }
+    #[structopt(
+        long,
+        default_value = "1024",
+        help = "The number of results that may wait for the output to be written"
+    )]
+    queue_capacity: usize,
 
-        p(&self.args, Vec::new(), name.to_string(), &mut res);
+    #[structopt(
+        long,
+        default_value = "block",
+        help = "What to do if the result queue is full: 'block' the workers or 'drop-oldest' results"
+    )]
+    queue_policy: QueuePolicy,
 
-        res.into_iter()
-            .map(|(args, name)| {
-                let mut cmd = Command::new(&self.command);
-                cmd.stdout(Stdio::piped());
-                for arg in args {
-                    cmd.arg(arg);
-                }
-                Cmd { command: cmd, name }
-            })
-            .collect()
-    }
-}
+    #[structopt(
+        long,
+        help = "Checks that the program of every task exists before running anything"
+    )]
+    verify_commands: bool,
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", content = "content")]
-enum Argument {
-    Static(String),
-    Range(RangeObject),
-    Choice(Vec<String>),
-}
+    #[structopt(
+        long,
+        default_value = "text",
+        help = "The format of the output records: 'text', 'json' (one object per line), \
+                'run-json' (one object for the whole run) or 'joblog' (like GNU parallel --joblog)"
+    )]
+    format: Format,
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-enum RangeObject {
-    Int {
-        from: i32,
-        to: i32,
-        step: i32,
-        prefix: Option<String>,
-    },
-    Float {
-        from: f64,
-        to: f64,
-        step: f64,
-        prefix: Option<String>,
-    },
-}
+    #[structopt(long, help = "Flushes the output after every record")]
+    unbuffered: bool,
 
-struct Cmd {
-    command: Command,
-    name: String,
-}
+    #[structopt(
+        long,
+        default_value = "64",
+        help = "Runs with at most this many tasks always flush the output after every record"
+    )]
+    unbuffered_threshold: usize,
+
+    #[structopt(
+        long,
+        help = "Samples the peak memory of every task from /proc every n milliseconds (Linux only)"
+    )]
+    mem_sample_interval: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Prints how busy each worker was during the run after the summary"
+    )]
+    worker_report: bool,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "The number of tasks a worker takes from the queue at once"
+    )]
+    pull_batch: usize,
+
+    /// Deprecated, see `prun plan --explain`.
+    #[structopt(long, hidden = true)]
+    explain: bool,
+
+    /// Deprecated, see `prun plan --summary`.
+    #[structopt(long, hidden = true)]
+    plan_summary: bool,
+
+    #[structopt(
+        long,
+        help = "Also sends every record to the system log: 'syslog' or 'journald' (Linux only)"
+    )]
+    output_target: Option<LogKind>,
+
+    #[structopt(
+        long,
+        help = "A shell command that receives every record as a line of JSON on its stdin"
+    )]
+    result_sink: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Writes the durations and exit codes of the tasks to a textfile of the Prometheus node exporter after the run"
+    )]
+    prometheus_textfile: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Records the names of the successful tasks in this file as they finish"
+    )]
+    checkpoint: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        requires = "checkpoint",
+        help = "Skips the tasks recorded in the --checkpoint file"
+    )]
+    resume: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Writes the stdout of every task to a file of its own in this directory"
+    )]
+    log_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Writes a shell script that reproduces every failed task into this directory"
+    )]
+    repro_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        requires = "log-dir",
+        help = "The maximum size of all logs in bytes, output beyond it is not captured"
+    )]
+    log_budget: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Measures the time from spawning each task until its first output (Unix only)"
+    )]
+    profile_spawn: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Watches this file for commands during the run, e.g. 'kill <task name>'"
+    )]
+    control_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Marks the first task of every worker as a warmup, which is left out of the statistics"
+    )]
+    discard_first_per_worker: bool,
+
+    #[structopt(
+        long,
+        default_value = "auto",
+        help = "When to color the output: 'auto' (if it goes to a terminal and NO_COLOR is unset), 'always' or 'never'"
+    )]
+    color: ColorChoice,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["db", "checkpoint"],
+        help = "Writes a placeholder record for every task without running anything, marked as simulated in the structured formats"
+    )]
+    simulate: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Runs only the tasks that are not part of this JSON plan, e.g. one printed by `prun plan` earlier"
+    )]
+    only_new: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Only starts a task while at least this many bytes of memory are available (Linux only)"
+    )]
+    min_free_memory: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Kills tasks running longer than this many seconds, see also --timeout-factor"
+    )]
+    timeout: Option<f64>,
+
+    #[structopt(
+        long,
+        help = "Kills tasks running longer than x times the median duration of the completed tasks, \
+                falling back to --timeout until enough tasks have completed"
+    )]
+    timeout_factor: Option<f64>,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Runs every task n times, in n rounds over all tasks: a round starts once the previous one has completed"
+    )]
+    repeat: usize,
+
+    #[structopt(
+        long,
+        help = "Reports the tasks whose repetitions wrote different stdout, see --repeat"
+    )]
+    verify_deterministic: bool,
+
+    #[structopt(
+        long,
+        help = "Also summarizes the metrics per value of this named dimension, across all tasks"
+    )]
+    group_by: Option<String>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        number_of_values = 1,
+        help = "Fails the run if a task captured a metric below a threshold, given as <metric>=<value>"
+    )]
+    fail_under: Vec<Threshold>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        number_of_values = 1,
+        help = "Fails the run if a task captured a metric above a threshold, given as <metric>=<value>"
+    )]
+    fail_over: Vec<Threshold>,
+}
+
+/// The tasks by name. Sorted, so tasks are always expanded and run in
+/// the same order.
+type Tasks = BTreeMap<String, Task>;
+
+/// Named bundles of arguments, referenced by `Argument::Preset`.
+type Presets = HashMap<String, Vec<String>>;
+
+/// Named groups of tasks, referenced by `Task::group`.
+type Groups = HashMap<String, Group>;
+
+/// A group of tasks, which shares the workers with the other groups in
+/// proportion to its weight, see `allocate_workers`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Group {
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// The config file. All top-level tables except `presets` and `groups`
+/// are tasks.
+#[derive(Serialize, Deserialize, Debug)]
+struct Config {
+    #[serde(default)]
+    presets: Presets,
+    #[serde(default)]
+    groups: Groups,
+    #[serde(flatten)]
+    tasks: Tasks,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Task {
+    id: Option<usize>,
+    #[serde(default)]
+    command: String,
+    /// Expanded into one concrete task per combination. The command and
+    /// arguments may refer to `${local_index}`, the position among the
+    /// concrete tasks of this task, and `${index}`, the position among
+    /// those of all tasks. Both count from 0 in the order of expansion,
+    /// i.e. with `--fair` in the interleaved order, and are assigned
+    /// before `--filter`, so a task keeps its index when others are
+    /// filtered. Barriers are not counted.
+    #[serde(default)]
+    args: Vec<Argument>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Metric names mapped to regexes with a single capture group, which
+    /// extract the metric values from the stdout of the task.
+    #[serde(default)]
+    capture_metrics: HashMap<String, String>,
+    /// Tasks that must have completed before this task starts.
+    #[serde(default)]
+    depends_on: Vec<String>,
+    /// Marks a task without a command, which completes as soon as its
+    /// dependencies have completed. Used to separate phases of a run.
+    #[serde(default)]
+    barrier: bool,
+    /// A shell command run once during expansion instead of expanding
+    /// `args`. Its stdout must be a JSON array of argument lists, each of
+    /// which becomes one concrete task.
+    generator: Option<String>,
+    /// The cores the task is restricted to (Linux only).
+    cpus: Option<Vec<usize>>,
+    /// The file the task reads, for `--incremental`. `${dim}` placeholders
+    /// are replaced with the values of the dimensions of each task, and
+    /// `${index}`, `${local_index}` and `${<task>.output}` like in the
+    /// arguments.
+    input: Option<String>,
+    /// The file the task writes, for `--incremental`. A template like
+    /// `input`.
+    output: Option<String>,
+    /// Argument overrides for the retries of a failed task, the n-th retry
+    /// runs with the n-th override. See `with_override`.
+    #[serde(default)]
+    retry_with: Vec<Vec<String>>,
+    /// The group the task belongs to, which must be declared in `groups`.
+    group: Option<String>,
+    /// A regex matched against the stdout and stderr of the task. Only if
+    /// it matches, the stdout is kept in the structured result.
+    keep_output_if: Option<String>,
+    /// Runs the concrete tasks of this task one at a time, while other
+    /// tasks still run in parallel.
+    #[serde(default)]
+    serial: bool,
+    /// The container image the task runs in, overriding `--container`.
+    /// A template like `input`, so images can be swept over.
+    container: Option<String>,
+    /// Named axes whose product is appended to the arguments, see `Matrix`.
+    matrix: Option<Matrix>,
+    /// Keeps the stdout of the task, without trailing whitespace, for the
+    /// tasks depending on it. Their arguments refer to it as
+    /// `${<task>.output}`. The outputs of several concrete tasks are
+    /// joined with spaces, in the order of the plan.
+    #[serde(default)]
+    capture_output: bool,
+    /// A file whose existence marks the task as done, checked right before
+    /// the task would start. A template like `input`. See `--force`.
+    skip_if_exists: Option<String>,
+}
+
+/// A matrix like in GitHub Actions. Every combination of the values of
+/// the axes runs as its own task, with the arguments `--<axis>=<value>`
+/// appended in the alphabetical order of the axes. Each axis is a named
+/// dimension.
+///
+/// ```toml
+/// [train.matrix]
+/// model = ["resnet", "vgg"]
+/// lr = [0.1, 0.01]
+/// exclude = [{ model = "vgg", lr = 0.1 }]
+/// include = [{ model = "vgg", batch = 32 }, { model = "vit", lr = 0.001 }]
+/// ```
+///
+/// `exclude` removes the combinations that match all values of an entry.
+/// Then, like in GitHub Actions, an entry of `include` extends every
+/// combination it matches on the axes with its other values, e.g. the vgg
+/// combinations with a batch of 32. Values added by an earlier entry may
+/// be overwritten, those of the axes never. An entry that matches no
+/// combination is added as a combination of its own.
+#[derive(Serialize, Deserialize, Debug)]
+struct Matrix {
+    #[serde(default)]
+    include: Vec<BTreeMap<String, toml::Value>>,
+    #[serde(default)]
+    exclude: Vec<BTreeMap<String, toml::Value>>,
+    #[serde(flatten)]
+    axes: BTreeMap<String, Vec<toml::Value>>,
+}
+
+impl Matrix {
+    /// The values of every combination, by axis.
+    fn combinations(&self, precision: Option<usize>) -> Result<Vec<Vec<(String, String)>>, String> {
+        let value = |axis: &str, value: &toml::Value| match value {
+            toml::Value::String(s) => Ok(s.clone()),
+            toml::Value::Integer(i) => Ok(i.to_string()),
+            toml::Value::Float(f) => Ok(format_float(*f, precision)),
+            toml::Value::Boolean(b) => Ok(b.to_string()),
+            _ => Err(format!(
+                "the values of axis '{}' must be strings, numbers or booleans",
+                axis
+            )),
+        };
+        let entry = |entry: &BTreeMap<String, toml::Value>| {
+            entry
+                .iter()
+                .map(|(axis, v)| Ok((axis.clone(), value(axis, v)?)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+
+        let mut combinations = Vec::new();
+        if !self.axes.is_empty() {
+            combinations.push(Vec::new());
+        }
+        for (axis, values) in &self.axes {
+            if values.is_empty() {
+                return Err(format!("axis '{}' has no values", axis));
+            }
+            let values = values
+                .iter()
+                .map(|v| value(axis, v))
+                .collect::<Result<Vec<_>, _>>()?;
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination: Vec<(String, String)>| {
+                    values.iter().map(move |v| {
+                        let mut next = combination.clone();
+                        next.push((axis.clone(), v.clone()));
+                        next
+                    })
+                })
+                .collect();
+        }
+
+        for exclude in &self.exclude {
+            if let Some(axis) = exclude.keys().find(|axis| !self.axes.contains_key(*axis)) {
+                return Err(format!("exclude refers to the unknown axis '{}'", axis));
+            }
+            let exclude = entry(exclude)?;
+            combinations.retain(|combination| !exclude.iter().all(|e| combination.contains(e)));
+        }
+        // Only the combinations of the axes are extended, not the added ones
+        let product = combinations.len();
+        for include in &self.include {
+            let include = entry(include)?;
+            if include.is_empty() {
+                return Err("include needs at least one value".to_string());
+            }
+            let (on_axes, extra): (Vec<_>, Vec<_>) = include
+                .iter()
+                .partition(|(axis, _)| self.axes.contains_key(axis));
+            let mut matched = false;
+            for combination in &mut combinations[..product] {
+                if !on_axes.iter().all(|value| combination.contains(value)) {
+                    continue;
+                }
+                matched = true;
+                for (axis, value) in &extra {
+                    match combination.iter_mut().find(|(a, _)| a == axis) {
+                        Some((_, v)) => *v = value.clone(),
+                        None => combination.push((axis.clone(), value.clone())),
+                    }
+                }
+                combination.sort();
+            }
+            if !matched {
+                combinations.push(include);
+            }
+        }
+        Ok(combinations)
+    }
+}
+
+impl Task {
+    #[allow(clippy::too_many_arguments)]
+    fn to_concreate_tasks(
+        &self,
+        task: &str,
+        env: &EnvPolicy,
+        presets: &Presets,
+        groups: &Groups,
+        precision: Option<usize>,
+        runtime: &str,
+        default_image: Option<&str>,
+    ) -> Result<Vec<Cmd>, String> {
+        let mut res = Vec::new();
+
+        fn p(
+            args: &[Argument],
+            idx: usize,
+            so_far: Partial,
+            presets: &Presets,
+            precision: Option<usize>,
+            res: &mut Vec<Partial>,
+        ) -> Result<(), String> {
+            if args.is_empty() {
+                res.push(so_far);
+                return Ok(());
+            }
+
+            match &args[0] {
+                Argument::Static(str) => p(
+                    &args[1..],
+                    idx + 1,
+                    so_far.push(str.clone(), str, None),
+                    presets,
+                    precision,
+                    res,
+                )?,
+                Argument::Choice(opts) => {
+                    let dim = format!("arg{}", idx);
+                    for (i, opt) in opts.iter().enumerate() {
+                        let origin = format!("Choice[{}]", i);
+                        let sf = so_far.push(opt.clone(), opt, Some((&dim, origin)));
+                        p(&args[1..], idx + 1, sf, presets, precision, res)?
+                    }
+                }
+                Argument::Preset { name } => {
+                    let (names, dim) = match name {
+                        OneOrMany::One(name) => (std::slice::from_ref(name), None),
+                        OneOrMany::Many(names) => (&names[..], Some(format!("arg{}", idx))),
+                    };
+                    for (i, name) in names.iter().enumerate() {
+                        let bundle = presets
+                            .get(name)
+                            .ok_or_else(|| format!("unknown preset '{}'", name))?;
+                        let dim = dim.as_deref().map(|dim| (dim, format!("Preset[{}]", i)));
+                        let sf = so_far.extend(bundle, name, dim);
+                        p(&args[1..], idx + 1, sf, presets, precision, res)?
+                    }
+                }
+                Argument::NumList { values, prefix } => {
+                    let dim = dim_name(prefix.as_deref(), idx);
+                    for (i, value) in values.iter().enumerate() {
+                        let value = format_float(*value, precision);
+                        let sf = so_far.push(
+                            format!("{}{}", prefix.as_deref().unwrap_or_default(), value),
+                            &value,
+                            Some((&dim, format!("NumList[{}]", i))),
+                        );
+                        p(&args[1..], idx + 1, sf, presets, precision, res)?
+                    }
+                }
+                Argument::Csv {
+                    path,
+                    column,
+                    prefix,
+                } => {
+                    let values = std::fs::read_to_string(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|s| csv::parse(&s))
+                        .and_then(|records| csv::column(&records, column))
+                        .map_err(|e| format!("invalid CSV file '{:?}': {}", path, e))?;
+                    let dim = match prefix {
+                        Some(prefix) => dim_name(Some(prefix), idx),
+                        None => dim_name(Some(column), idx),
+                    };
+                    for (i, value) in values.iter().enumerate() {
+                        let sf = so_far.push(
+                            format!("{}{}", prefix.as_deref().unwrap_or_default(), value),
+                            value,
+                            Some((&dim, format!("Csv[{}]", i))),
+                        );
+                        p(&args[1..], idx + 1, sf, presets, precision, res)?
+                    }
+                }
+                Argument::Range(range) => match range {
+                    RangeObject::Int {
+                        from,
+                        to,
+                        step,
+                        prefix,
+                    } => {
+                        let dim = dim_name(prefix.as_deref(), idx);
+                        let mut c = *from;
+                        while c <= *to {
+                            let sf = so_far.push(
+                                format!("{}{}", prefix.as_ref().unwrap_or(&String::new()), c),
+                                &format!("{}", c),
+                                Some((&dim, "Range".to_string())),
+                            );
+
+                            p(&args[1..], idx + 1, sf, presets, precision, res)?;
+                            c += *step;
+                        }
+                    }
+                    RangeObject::Float {
+                        from,
+                        to,
+                        step,
+                        prefix,
+                    } => {
+                        if *step <= 0.0 {
+                            return Err(format!("float range needs a step > 0, got {}", step));
+                        }
+                        let dim = dim_name(prefix.as_deref(), idx);
+                        // From the index rather than by adding up the step, which
+                        // drifts, e.g. 0.1 + 0.1 + 0.1 > 0.3. The slack keeps `to`
+                        // where (to - from) / step is just below a whole number.
+                        let count = match to - from {
+                            span if span < 0.0 => 0,
+                            span => (span / step + 1e-9).floor() as usize + 1,
+                        };
+                        for i in 0..count {
+                            let value = format_float(from + i as f64 * step, precision);
+                            let sf = so_far.push(
+                                format!("{}{}", prefix.as_ref().unwrap_or(&String::new()), value),
+                                &value,
+                                Some((&dim, "Range".to_string())),
+                            );
+
+                            p(&args[1..], idx + 1, sf, presets, precision, res)?;
+                        }
+                    }
+                    RangeObject::Geometric {
+                        from,
+                        ratio,
+                        count,
+                        prefix,
+                    } => {
+                        if *ratio <= 0.0 {
+                            return Err(format!(
+                                "geometric range needs a ratio > 0, got {}",
+                                ratio
+                            ));
+                        }
+                        if *count == 0 {
+                            return Err("geometric range needs a count of at least 1".to_string());
+                        }
+                        let dim = dim_name(prefix.as_deref(), idx);
+                        let mut values = (0..*count)
+                            .map(|i| from * ratio.powi(i as i32))
+                            .collect::<Vec<_>>();
+                        // Powers of fractional ratios are inexact, e.g. 1000 * 1.1^3
+                        if values.iter().all(|v| (v - v.round()).abs() < 1e-9) {
+                            values.iter_mut().for_each(|v| *v = v.round());
+                        }
+                        for value in values {
+                            let value = format_float(value, precision);
+                            let sf = so_far.push(
+                                format!("{}{}", prefix.as_deref().unwrap_or_default(), value),
+                                &value,
+                                Some((&dim, "Range".to_string())),
+                            );
+                            p(&args[1..], idx + 1, sf, presets, precision, res)?;
+                        }
+                    }
+                },
+            }
+            Ok(())
+        }
+
+        let base = Partial {
+            args: Vec::new(),
+            name: task.to_string(),
+            dims: Vec::new(),
+            origins: Vec::new(),
+        };
+        match &self.generator {
+            Some(_) if !self.args.is_empty() => {
+                return Err(format!(
+                "Failed to expand task '{}': a generator replaces args, so both cannot be given",
+                task
+            ))
+            }
+            Some(generator) => {
+                let generated = run_generator(generator, env)
+                    .map_err(|e| format!("Failed to expand task '{}': {}", task, e))?;
+                for (i, args) in generated.iter().enumerate() {
+                    let mut sf = base.clone();
+                    for (idx, arg) in args.iter().enumerate() {
+                        let dim = format!("arg{}", idx);
+                        sf = sf.push(arg.clone(), arg, Some((&dim, format!("Generator[{}]", i))));
+                    }
+                    res.push(sf);
+                }
+            }
+            None => p(&self.args, 0, base, presets, precision, &mut res)
+                .map_err(|e| format!("Failed to expand task '{}': {}", task, e))?,
+        }
+
+        if let Some(matrix) = &self.matrix {
+            let combinations = matrix
+                .combinations(precision)
+                .map_err(|e| format!("Invalid matrix of task '{}': {}", task, e))?;
+            res = res
+                .iter()
+                .flat_map(|partial| {
+                    combinations.iter().enumerate().map(|(i, combination)| {
+                        combination
+                            .iter()
+                            .fold(partial.clone(), |sf, (axis, value)| {
+                                let origin = format!("Matrix[{}]", i);
+                                sf.push(
+                                    format!("--{}={}", axis, value),
+                                    value,
+                                    Some((axis, origin)),
+                                )
+                            })
+                    })
+                })
+                .collect();
+        }
+
+        let capture = compile_metrics(&self.capture_metrics)
+            .map_err(|e| format!("Invalid metrics of task '{}': {}", task, e))?;
+        let keep_output_if = self
+            .keep_output_if
+            .as_deref()
+            .map(|pattern| Regex::new(pattern).map(Arc::new))
+            .transpose()
+            .map_err(|e| format!("Invalid keep_output_if of task '{}': {}", task, e))?;
+
+        let cpus = self.cpus.as_deref().and_then(|cpus| valid_cpus(cpus, task));
+        let weight = self
+            .group
+            .as_ref()
+            .and_then(|group| groups.get(group))
+            .map_or(1, |group| group.weight);
+
+        let render = |template: &Option<String>, dims: &[(String, String)]| {
+            template
+                .as_deref()
+                .map(|t| render_template(t, dims).map(PathBuf::from))
+                .transpose()
+                .map_err(|e| format!("Failed to expand task '{}': {}", task, e))
+        };
+
+        res.into_iter()
+            .enumerate()
+            .map(
+                |(
+                    index,
+                    Partial {
+                        args,
+                        name,
+                        dims,
+                        origins,
+                    },
+                )| {
+                    let container = render(&self.container, &dims)?
+                        .map(|image| image.to_string_lossy().into_owned())
+                        .or_else(|| default_image.filter(|_| !self.barrier).map(str::to_string))
+                        .map(|image| Container {
+                            runtime: runtime.to_string(),
+                            image,
+                        });
+                    let mut cmd = Cmd {
+                        retry_with: self
+                            .retry_with
+                            .iter()
+                            .map(|overrides| with_override(&args, overrides))
+                            .collect(),
+                        input: render(&self.input, &dims)?,
+                        output: render(&self.output, &dims)?,
+                        skip_if_exists: render(&self.skip_if_exists, &dims)?,
+                        env: env.resolve(&self.env, container.is_some()),
+                        container,
+                        program: self.command.clone(),
+                        args,
+                        clear_env: env.clean,
+                        cwd: None,
+                        name,
+                        task: task.to_string(),
+                        dims,
+                        capture: capture.clone(),
+                        depends_on: self.depends_on.clone(),
+                        barrier: self.barrier,
+                        origins,
+                        cpus: cpus.clone(),
+                        group: self.group.clone(),
+                        weight,
+                        keep_output_if: keep_output_if.clone(),
+                        serial: self.serial,
+                        capture_output: self.capture_output,
+                        repeat: None,
+                        position: 0,
+                        index: 0,
+                    };
+                    cmd.substitute_index("${local_index}", index);
+                    Ok(cmd)
+                },
+            )
+            .collect()
+    }
+}
+
+/// Applies the override of a retry to the arguments of a task. An
+/// override `--key=value` replaces the argument starting with `--key=`,
+/// other overrides replace an equal argument. Overrides without such an
+/// argument are appended.
+fn with_override(args: &[String], overrides: &[String]) -> Vec<String> {
+    let mut args = args.to_vec();
+    for arg in overrides {
+        let key = match arg.find('=') {
+            Some(idx) => &arg[..=idx],
+            None => arg.as_str(),
+        };
+        let matches = |a: &String| {
+            if key.ends_with('=') {
+                a.starts_with(key)
+            } else {
+                a == key
+            }
+        };
+        match args.iter_mut().find(|a| matches(a)) {
+            Some(a) => *a = arg.clone(),
+            None => args.push(arg.clone()),
+        }
+    }
+    args
+}
+
+/// Substitutes the `${dim}` placeholders of a template with the values of
+/// the named dimensions of a task. The placeholders of the arguments,
+/// `${index}`, `${local_index}` and `${<task>.output}`, are kept, since
+/// they are only known later, see `Cmd::substitute`.
+fn render_template(template: &str, dims: &[(String, String)]) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '${{' in template '{}'", template))?;
+        let dim = &rest[start + 2..start + end];
+        if dim == "index" || dim == "local_index" || dim.ends_with(".output") {
+            rendered.push_str(&rest[start..=start + end]);
+        } else {
+            let (_, value) = dims
+                .iter()
+                .find(|(name, _)| name == dim)
+                .ok_or_else(|| format!("unknown dimension '{}' in template '{}'", dim, template))?;
+            rendered.push_str(value);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Prints how many tasks each value of a dimension contributes, per task
+/// of the config, e.g. `model: resnet=40, vgg=40`. Dimensions and values
+/// are listed in the order they first appear in the plan.
+fn print_plan_summary(tasks: &VecDeque<Cmd>) {
+    type Dims<'a> = Vec<(&'a str, Vec<(&'a str, usize)>)>;
+    let mut summary: Vec<(&str, usize, Dims)> = Vec::new();
+    for cmd in tasks {
+        let idx = match summary.iter().position(|(task, ..)| *task == cmd.task) {
+            Some(idx) => idx,
+            None => {
+                summary.push((&cmd.task, 0, Vec::new()));
+                summary.len() - 1
+            }
+        };
+        let (_, count, dims) = &mut summary[idx];
+        *count += 1;
+        for (dim, value) in &cmd.dims {
+            let values = match dims.iter_mut().find(|(d, _)| d == dim) {
+                Some((_, values)) => values,
+                None => {
+                    dims.push((dim, Vec::new()));
+                    &mut dims.last_mut().unwrap().1
+                }
+            };
+            match values.iter_mut().find(|(v, _)| v == value) {
+                Some((_, n)) => *n += 1,
+                None => values.push((value, 1)),
+            }
+        }
+    }
+
+    for (task, count, dims) in summary {
+        println!("{} ({} tasks)", task, count);
+        for (dim, values) in dims {
+            let values = values
+                .iter()
+                .map(|(v, n)| format!("{}={}", v, n))
+                .collect::<Vec<_>>();
+            println!("  {}: {}", dim, values.join(", "));
+        }
+    }
+    println!("Total: {} tasks", tasks.len());
+}
+
+/// Shuffles the tasks with a Fisher-Yates shuffle driven by splitmix64.
+///
+/// Tasks are usually expanded in order, so neighbouring tasks differ in
+/// one dimension only and run at about the same time and on adjacent
+/// workers. Effects of the time within the run (warm caches, thermal
+/// throttling, other load) then look like effects of that dimension. A
+/// uniformly random order turns them into noise which is spread evenly
+/// over all tasks. The same seed always yields the same order.
+fn shuffle(tasks: &mut VecDeque<Cmd>, seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let tasks = tasks.make_contiguous();
+    for i in (1..tasks.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        tasks.swap(i, j);
+    }
+}
+
+/// Merges the expansions of several tasks round-robin, so the queue
+/// alternates between them until the smaller ones are exhausted.
+fn interleave(expansions: Vec<Vec<Cmd>>) -> VecDeque<Cmd> {
+    let mut iters = expansions
+        .into_iter()
+        .map(Vec::into_iter)
+        .collect::<Vec<_>>();
+    let mut queue = VecDeque::new();
+    loop {
+        let len = queue.len();
+        queue.extend(iters.iter_mut().filter_map(Iterator::next));
+        if queue.len() == len {
+            return queue;
+        }
+    }
+}
+
+/// Compiles the metric regexes of a task, sorted by the metric name.
+fn compile_metrics(
+    patterns: &HashMap<String, String>,
+) -> Result<Arc<Vec<(String, Regex)>>, String> {
+    let mut metrics = patterns
+        .iter()
+        .map(|(name, pattern)| {
+            let regex = Regex::new(pattern).map_err(|e| format!("metric '{}': {}", name, e))?;
+            if regex.groups() != 1 {
+                return Err(format!(
+                    "metric '{}': regex '{}' must have exactly one capture group",
+                    name, pattern
+                ));
+            }
+            Ok((name.clone(), regex))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    metrics.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(Arc::new(metrics))
+}
+
+/// Runs the generator of a task through the shell and parses its stdout,
+/// which must be a JSON array of argument lists. Numbers and booleans are
+/// accepted as arguments and formatted like in the generator's output.
+fn run_generator(cmd: &str, env: &EnvPolicy) -> Result<Vec<Vec<String>>, String> {
+    let mut command = Command::new("sh");
+    if env.clean {
+        command.env_clear();
+    }
+    command.envs(env.resolve(&HashMap::new(), false));
+    let out = command
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("failed to run generator '{}': {}", cmd, e))?;
+    if !out.status.success() {
+        return Err(format!("generator '{}' failed ({})", cmd, out.status));
+    }
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let json = Json::parse(&stdout)
+        .map_err(|e| format!("generator '{}' printed invalid JSON: {}", cmd, e))?;
+    let entries = json
+        .as_array()
+        .ok_or_else(|| format!("generator '{}' must print a JSON array", cmd))?;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let invalid = || {
+                format!(
+                    "entry {} of generator '{}' must be an array of strings, numbers or booleans",
+                    i, cmd
+                )
+            };
+            entry
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|arg| match arg {
+                    Json::Str(s) => Ok(s.clone()),
+                    Json::Num(_) | Json::Bool(_) => Ok(arg.to_string()),
+                    _ => Err(invalid()),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// A partially expanded task.
+#[derive(Clone)]
+struct Partial {
+    args: Vec<String>,
+    name: String,
+    dims: Vec<(String, String)>,
+    /// The kind of argument that spanned each dimension, and the index of
+    /// the chosen value for lists (e.g. `Choice[1]`).
+    origins: Vec<String>,
+}
+
+impl Partial {
+    fn push(&self, arg: String, value: &str, dim: Option<(&str, String)>) -> Partial {
+        self.extend(std::slice::from_ref(&arg), value, dim)
+    }
+
+    fn extend(&self, args: &[String], value: &str, dim: Option<(&str, String)>) -> Partial {
+        let mut next = self.clone();
+        next.args.extend_from_slice(args);
+        next.name.push(',');
+        next.name.push_str(value);
+        if let Some((dim, origin)) = dim {
+            next.dims.push((dim.to_string(), value.to_string()));
+            next.origins.push(origin);
+        }
+        next
+    }
+}
+
+/// The default number of decimals of float values, see `format_float`.
+const FLOAT_PRECISION: usize = 6;
+
+/// Formats a float value of an argument, which is also part of the task
+/// name. With a precision, the value always has exactly that many
+/// decimals (e.g. `0.100`). Otherwise it is rounded to `FLOAT_PRECISION`
+/// decimals without trailing zeros, which hides the inexact results of
+/// float arithmetic (`0.30000000000000004` becomes `0.3`) and leaves
+/// whole numbers without a fractional part.
+fn format_float(value: f64, precision: Option<usize>) -> String {
+    if let Some(precision) = precision {
+        return format!("{:.*}", precision, value);
+    }
+    let s = format!("{:.*}", FLOAT_PRECISION, value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// The name of the dimension spanned by the argument at position `idx`.
+/// Ranges with a prefix like `--lr=` are named after the flag (`lr`),
+/// everything else after its position (`arg2`).
+fn dim_name(prefix: Option<&str>, idx: usize) -> String {
+    let name = prefix
+        .unwrap_or_default()
+        .trim_start_matches('-')
+        .trim_end_matches(|c: char| !c.is_alphanumeric());
+    if name.is_empty() {
+        format!("arg{}", idx)
+    } else {
+        name.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "content")]
+enum Argument {
+    Static(String),
+    Range(RangeObject),
+    Choice(Vec<String>),
+    /// Expands inline into the arguments of a preset. A list of
+    /// preset names sweeps over the bundles, like a `Choice`.
+    Preset {
+        name: OneOrMany,
+    },
+    /// Irregularly spaced numbers, prefixed like a `Range`. Formatted like
+    /// the values of a float range, so whole numbers have no fractional
+    /// part unless `--float-precision` is given.
+    NumList {
+        values: Vec<f64>,
+        prefix: Option<String>,
+    },
+    /// The values of a column of a CSV file, selected by its header.
+    Csv {
+        path: PathBuf,
+        column: String,
+        prefix: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum RangeObject {
+    Int {
+        from: i32,
+        to: i32,
+        step: i32,
+        prefix: Option<String>,
+    },
+    Float {
+        from: f64,
+        to: f64,
+        step: f64,
+        prefix: Option<String>,
+    },
+    /// `count` values, starting at `from` and multiplied by `ratio` in
+    /// every step, e.g. 32, 64, 128, 256.
+    Geometric {
+        from: f64,
+        ratio: f64,
+        count: usize,
+        prefix: Option<String>,
+    },
+}
+
+/// The variables kept with `--clean-env` unless `--env-keep` is given.
+const DEFAULT_ENV_KEEP: &[&str] = &["PATH", "HOME"];
+
+/// Decides which variables of the parent environment are inherited by
+/// the spawned tasks. Variables declared by a task are always set.
+struct EnvPolicy {
+    clean: bool,
+    /// The variables of `--env-keep`, empty to keep `DEFAULT_ENV_KEEP`.
+    keep: Vec<String>,
+}
+
+impl EnvPolicy {
+    /// The variables to set explicitly on a child, given the variables
+    /// declared by its task. With a clean environment this includes the
+    /// current values of the kept variables.
+    ///
+    /// In a container, `DEFAULT_ENV_KEEP` is not kept, since the `PATH`
+    /// and `HOME` of the host would break the ones of the image. Only
+    /// variables given with `--env-keep` are passed on.
+    fn resolve(
+        &self,
+        declared: &HashMap<String, String>,
+        container: bool,
+    ) -> Vec<(String, String)> {
+        let keep = match self.keep.is_empty() {
+            true if !container => DEFAULT_ENV_KEEP.iter().map(|key| key.to_string()).collect(),
+            _ => self.keep.clone(),
+        };
+        let mut env = Vec::new();
+        if self.clean {
+            for key in &keep {
+                if let Ok(value) = std::env::var(key) {
+                    env.push((key.clone(), value));
+                }
+            }
+        }
+
+        let mut declared = declared.iter().collect::<Vec<_>>();
+        declared.sort();
+        env.extend(declared.into_iter().map(|(k, v)| (k.clone(), v.clone())));
+        env
+    }
+}
+
+/// A concrete task, ready to be spawned.
+#[derive(Clone)]
+struct Cmd {
+    program: String,
+    args: Vec<String>,
+    /// Variables set on the child, on top of the inherited environment
+    /// unless `clear_env` is set.
+    env: Vec<(String, String)>,
+    clear_env: bool,
+    cwd: Option<PathBuf>,
+    name: String,
+    task: String,
+    dims: Vec<(String, String)>,
+    /// The metrics to extract from stdout.
+    capture: Arc<Vec<(String, Regex)>>,
+    depends_on: Vec<String>,
+    barrier: bool,
+    /// How each of the dims was derived, see `Partial::origins`. Not part
+    /// of a plan, so empty for replayed tasks.
+    origins: Vec<String>,
+    cpus: Option<Vec<usize>>,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+    /// The arguments of each retry, see `Task::retry_with`.
+    retry_with: Vec<Vec<String>>,
+    group: Option<String>,
+    /// The weight of the group, 1 for tasks without a group.
+    weight: u32,
+    /// Captures stderr as well, see `Task::keep_output_if`.
+    keep_output_if: Option<Arc<Regex>>,
+    serial: bool,
+    container: Option<Container>,
+    /// See `Task::capture_output`.
+    capture_output: bool,
+    skip_if_exists: Option<PathBuf>,
+    /// The round of the task with `--repeat`, counting from 0.
+    repeat: Option<usize>,
+    /// The position of the task in the run, set by the `Scheduler`.
+    position: usize,
+    /// The position among the concrete tasks before any filtering, the
+    /// value of `${index}`. Numbers the records of a joblog.
+    index: usize,
+}
+
+impl Cmd {
+    fn command(&self) -> Command {
+        self.command_with(&self.args)
+    }
+
+    /// Replaces a placeholder in the program and all arguments, including
+    /// those of the retries, and in the templates of `Task`, i.e. the
+    /// paths and the image of the container.
+    fn substitute(&mut self, placeholder: &str, value: &str) {
+        let args = self
+            .args
+            .iter_mut()
+            .chain(self.retry_with.iter_mut().flatten());
+        for arg in std::iter::once(&mut self.program).chain(args) {
+            if arg.contains(placeholder) {
+                *arg = arg.replace(placeholder, value);
+            }
+        }
+        let paths = [&mut self.input, &mut self.output, &mut self.skip_if_exists];
+        for path in paths.into_iter().flatten() {
+            let path_str = path.to_string_lossy();
+            if path_str.contains(placeholder) {
+                *path = PathBuf::from(path_str.replace(placeholder, value));
+            }
+        }
+        if let Some(container) = &mut self.container {
+            container.image = container.image.replace(placeholder, value);
+        }
+    }
+
+    /// Replaces an index placeholder like `${index}`, also in the name,
+    /// which keeps the names of tasks that only differ by it unique.
+    fn substitute_index(&mut self, placeholder: &str, index: usize) {
+        let index = index.to_string();
+        self.substitute(placeholder, &index);
+        self.name = self.name.replace(placeholder, &index);
+    }
+
+    /// A shell script that runs this task with the given arguments like
+    /// prun does, for `--repro-dir`. Without an explicit `cwd` the script
+    /// changes into the current directory.
+    fn repro_script(&self, args: &[String], status: &ExitStatus) -> String {
+        let cwd = self.cwd.clone().or_else(|| std::env::current_dir().ok());
+        let mut command = vec!["exec".to_string()];
+        if let Some(container) = &self.container {
+            command.push(shell_quote(&container.runtime));
+            let dir = cwd.clone().unwrap_or_default();
+            let wrapped =
+                container.args(&self.program, args, &self.env, &dir, self.cpus.as_deref());
+            command.extend(wrapped.iter().map(|a| shell_quote(a)));
+        } else {
+            if self.clear_env || !self.env.is_empty() {
+                command.push("env".to_string());
+            }
+            if self.clear_env {
+                command.push("-i".to_string());
+            }
+            for (key, value) in &self.env {
+                command.push(shell_quote(&format!("{}={}", key, value)));
+            }
+            command.push(shell_quote(&self.program));
+            command.extend(args.iter().map(|a| shell_quote(a)));
+        }
+
+        let mut script = format!(
+            "#!/bin/sh\n# Reproduces the task {:?} of prun, which failed with {}\n",
+            self.name, status
+        );
+        if let Some(cwd) = cwd {
+            script += &format!("cd {} || exit\n", shell_quote(&cwd.to_string_lossy()));
+        }
+        script + &command.join(" ") + "\n"
+    }
+
+    /// What makes tasks the same for `--only-new`: the program with its
+    /// arguments and environment, regardless of the name of the task.
+    fn identity(&self) -> String {
+        let mut identity = vec![
+            Json::from(self.program.as_str()),
+            Json::from(self.args.clone()),
+            pairs_json(&self.env),
+        ];
+        // Only part of it if set, so the identities of earlier plans hold
+        if let Some(container) = &self.container {
+            identity.push(Json::from(container.image.as_str()));
+        }
+        Json::Arr(identity).to_string()
+    }
+
+    /// The command of this task, run with different arguments.
+    fn command_with(&self, args: &[String]) -> Command {
+        let mut cmd = match &self.container {
+            // The runtime itself keeps the environment of prun, the variables
+            // and cores of the task apply inside of the container
+            Some(container) => {
+                let mut cmd = Command::new(&container.runtime);
+                let cwd = match &self.cwd {
+                    Some(cwd) => cwd.clone(),
+                    None => std::env::current_dir().unwrap_or_default(),
+                };
+                cmd.args(container.args(
+                    &self.program,
+                    args,
+                    &self.env,
+                    &cwd,
+                    self.cpus.as_deref(),
+                ));
+                cmd
+            }
+            None => {
+                let mut cmd = Command::new(&self.program);
+                if self.clear_env {
+                    cmd.env_clear();
+                }
+                cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+                cmd.args(args);
+                cmd
+            }
+        };
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.stdout(Stdio::piped());
+        if self.keep_output_if.is_some() {
+            cmd.stderr(Stdio::piped());
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(cpus) = self.cpus.clone().filter(|_| self.container.is_none()) {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: only calls sched_setaffinity, which is async-signal-safe
+            unsafe {
+                cmd.pre_exec(move || set_affinity(&cpus));
+            }
+        }
+        cmd
+    }
+
+    /// Whether the output of the task exists and is newer than its input,
+    /// like make decides on rebuilding. Tasks without an output are never
+    /// up to date, tasks without an input as soon as the output exists.
+    fn is_up_to_date(&self) -> bool {
+        let Some(output) = &self.output else {
+            return false;
+        };
+        let mtime = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+        match (mtime(output), self.input.as_deref()) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(output), Some(input)) => mtime(input).is_some_and(|input| output >= input),
+        }
+    }
+
+    /// Describes which top-level task and which argument values produced
+    /// this task, e.g. `from task train: lr=0.01 (Range)`.
+    fn explain(&self) -> String {
+        if self.barrier {
+            return format!("barrier {}", self.task);
+        }
+
+        let dims = self
+            .dims
+            .iter()
+            .enumerate()
+            .map(|(i, (dim, value))| match self.origins.get(i) {
+                Some(origin) => format!("{}={} ({})", dim, value, origin),
+                None => format!("{}={}", dim, value),
+            })
+            .collect::<Vec<_>>();
+        if dims.is_empty() {
+            format!("from task {}", self.task)
+        } else {
+            format!("from task {}: {}", self.task, dims.join(", "))
+        }
+    }
+
+    /// The plan entry of this task, carrying everything needed to replay
+    /// it without the config. A missing `cwd` is recorded as the current
+    /// working directory.
+    fn to_json(&self) -> Json {
+        let cwd = self.cwd.clone().or_else(|| std::env::current_dir().ok());
+        let path = |path: &Option<PathBuf>| path.as_ref().map(|p| p.to_string_lossy().to_string());
+        Json::obj()
+            .with("name", self.name.as_str())
+            .with("task", self.task.as_str())
+            .with("index", self.index as u64)
+            .with("program", self.program.as_str())
+            .with("args", self.args.clone())
+            .with("env", pairs_json(&self.env))
+            .with("clear_env", self.clear_env)
+            .with("cwd", path(&cwd))
+            .with("dims", pairs_json(&self.dims))
+            .with("depends_on", self.depends_on.clone())
+            .with("barrier", self.barrier)
+            .with("serial", self.serial)
+            .with("capture_output", self.capture_output)
+            .with("cpus", self.cpus.clone())
+            .with("input", path(&self.input))
+            .with("output", path(&self.output))
+            .with("skip_if_exists", path(&self.skip_if_exists))
+            .with("retry_with", self.retry_with.clone())
+            .with("group", self.group.clone())
+            .with("weight", self.weight as u64)
+            .with(
+                "keep_output_if",
+                self.keep_output_if.as_ref().map(|re| re.as_str()),
+            )
+            .with(
+                "container",
+                self.container.as_ref().map(|container| {
+                    Json::obj()
+                        .with("runtime", container.runtime.as_str())
+                        .with("image", container.image.as_str())
+                }),
+            )
+            .with(
+                "capture_metrics",
+                self.capture
+                    .iter()
+                    .fold(Json::obj(), |json, (name, re)| json.with(name, re.as_str())),
+            )
+    }
+
+    fn from_json(json: &Json) -> Result<Cmd, String> {
+        fn str_field(json: &Json, key: &str) -> Result<String, String> {
+            json.get(key)
+                .and_then(Json::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("missing string field '{}'", key))
+        }
+
+        fn pairs_field(json: &Json, key: &str) -> Result<Vec<(String, String)>, String> {
+            match json.get(key) {
+                None | Some(Json::Null) => Ok(Vec::new()),
+                Some(value) => value
+                    .as_object()
+                    .and_then(|fields| {
+                        fields
+                            .iter()
+                            .map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                            .collect()
+                    })
+                    .ok_or_else(|| format!("field '{}' must be an object of strings", key)),
+            }
+        }
+
+        fn strings(json: &Json) -> Option<Vec<String>> {
+            json.as_array()?
+                .iter()
+                .map(|a| a.as_str().map(str::to_string))
+                .collect()
+        }
+
+        let args = json
+            .get("args")
+            .and_then(strings)
+            .ok_or("field 'args' must be an array of strings")?;
+
+        Ok(Cmd {
+            program: str_field(json, "program")?,
+            args,
+            env: pairs_field(json, "env")?,
+            clear_env: json
+                .get("clear_env")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            cwd: json.get("cwd").and_then(Json::as_str).map(PathBuf::from),
+            name: str_field(json, "name")?,
+            task: str_field(json, "task")?,
+            dims: pairs_field(json, "dims")?,
+            capture: compile_metrics(&pairs_field(json, "capture_metrics")?.into_iter().collect())?,
+            depends_on: match json.get("depends_on") {
+                None | Some(Json::Null) => Vec::new(),
+                Some(deps) => deps
+                    .as_array()
+                    .and_then(|deps| {
+                        deps.iter()
+                            .map(|d| d.as_str().map(str::to_string))
+                            .collect::<Option<Vec<_>>>()
+                    })
+                    .ok_or("field 'depends_on' must be an array of strings")?,
+            },
+            barrier: json.get("barrier").and_then(Json::as_bool).unwrap_or(false),
+            serial: json.get("serial").and_then(Json::as_bool).unwrap_or(false),
+            capture_output: json
+                .get("capture_output")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            origins: Vec::new(),
+            cpus: match json.get("cpus") {
+                None | Some(Json::Null) => None,
+                Some(cpus) => {
+                    let cpus = cpus
+                        .as_array()
+                        .and_then(|cpus| {
+                            cpus.iter()
+                                .map(|c| match c {
+                                    Json::Num(n) if n.fract() == 0.0 && *n >= 0.0 => {
+                                        Some(*n as usize)
+                                    }
+                                    _ => None,
+                                })
+                                .collect::<Option<Vec<_>>>()
+                        })
+                        .ok_or("field 'cpus' must be an array of core indices")?;
+                    valid_cpus(&cpus, &str_field(json, "name")?)
+                }
+            },
+            input: json.get("input").and_then(Json::as_str).map(PathBuf::from),
+            output: json.get("output").and_then(Json::as_str).map(PathBuf::from),
+            skip_if_exists: json
+                .get("skip_if_exists")
+                .and_then(Json::as_str)
+                .map(PathBuf::from),
+            repeat: None,
+            position: 0,
+            index: match json.get("index") {
+                None | Some(Json::Null) => 0,
+                Some(Json::Num(i)) if i.fract() == 0.0 && *i >= 0.0 => *i as usize,
+                Some(_) => return Err("field 'index' must be a non-negative integer".to_string()),
+            },
+            retry_with: match json.get("retry_with") {
+                None | Some(Json::Null) => Vec::new(),
+                Some(retries) => retries
+                    .as_array()
+                    .and_then(|retries| retries.iter().map(strings).collect())
+                    .ok_or("field 'retry_with' must be an array of argument lists")?,
+            },
+            group: json.get("group").and_then(Json::as_str).map(str::to_string),
+            weight: match json.get("weight") {
+                None | Some(Json::Null) => 1,
+                Some(Json::Num(w)) if w.fract() == 0.0 && *w >= 1.0 => *w as u32,
+                Some(_) => return Err("field 'weight' must be a positive integer".to_string()),
+            },
+            keep_output_if: json
+                .get("keep_output_if")
+                .and_then(Json::as_str)
+                .map(|pattern| Regex::new(pattern).map(Arc::new))
+                .transpose()?,
+            container: match json.get("container") {
+                None | Some(Json::Null) => None,
+                Some(container) => Some(Container {
+                    runtime: str_field(container, "runtime")?,
+                    image: str_field(container, "image")?,
+                }),
+            },
+        })
+    }
+}
+
+/// Drops the cores of a task that do not exist, with a warning. Returns
+/// `None` if no core is left, or if affinity is not supported.
+fn valid_cpus(cpus: &[usize], task: &str) -> Option<Vec<usize>> {
+    if cfg!(not(target_os = "linux")) {
+        eprintln!(
+            "[PRUN] Ignoring the cpus of task '{}', since CPU affinity is only supported on Linux",
+            task
+        );
+        return None;
+    }
+
+    let available = num_cpus::get();
+    let (valid, invalid): (Vec<usize>, Vec<usize>) = cpus.iter().partition(|&&cpu| cpu < available);
+    if !invalid.is_empty() {
+        eprintln!(
+            "[PRUN] Ignoring cpus {:?} of task '{}', only {} cores are available",
+            invalid, task, available
+        );
+    }
+    Some(valid).filter(|valid| !valid.is_empty())
+}
+
+/// Restricts the calling process to the given cores.
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    // SAFETY: cpu_set_t is a plain bit set, for which all zeros is empty
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        // SAFETY: the cores were checked against the available cores
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: `set` is a valid cpu_set_t of the given size
+    let ret = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+fn pairs_json(pairs: &[(String, String)]) -> Json {
+    pairs
+        .iter()
+        .fold(Json::obj(), |json, (k, v)| json.with(k, v.as_str()))
+}
+
+/// Loads the concrete tasks of a plan printed by `prun plan`.
+/// Either the plan object or a bare array of its `tasks` is accepted.
+fn load_plan(path: &Path) -> Result<VecDeque<Cmd>, String> {
+    let string = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read plan '{:?}': {}", path, e))?;
+    let json =
+        Json::parse(&string).map_err(|e| format!("Failed to parse plan '{:?}': {}", path, e))?;
+
+    let tasks = json
+        .get("tasks")
+        .unwrap_or(&json)
+        .as_array()
+        .ok_or_else(|| format!("Plan '{:?}' contains no task array", path))?;
+
+    // Plans of older versions have no index, their tasks are numbered in
+    // the order of the plan like `${index}`
+    let mut index = 0;
+    tasks
+        .iter()
+        .enumerate()
+        .map(|(i, task)| {
+            let mut cmd = Cmd::from_json(task)
+                .map_err(|e| format!("Invalid task #{} in plan '{:?}': {}", i, path, e))?;
+            if matches!(task.get("index"), None | Some(Json::Null)) && !cmd.barrier {
+                cmd.index = index;
+            }
+            index += usize::from(!cmd.barrier);
+            Ok(cmd)
+        })
+        .collect()
+}
+
+/// The outcome of a single concrete task, as reported by the workers
+/// to the writer thread.
+struct TaskResult {
+    name: String,
+    task: String,
+    args: Vec<String>,
+    dims: Vec<(String, String)>,
+    duration: Duration,
+    exit_code: Option<i32>,
+    /// The last line of stdout, if captured with `--stdout`.
+    metric: Option<String>,
+    usage: Option<Usage>,
+    /// The values of the captured metrics.
+    metrics: Vec<(String, String)>,
+    /// The captured metrics whose regex did not match.
+    unmatched_metrics: Vec<String>,
+    /// The sampled peak resident set size in KiB, see `--mem-sample-interval`.
+    peak_rss: Option<u64>,
+    /// The completion time as a unix timestamp in seconds.
+    timestamp: u64,
+    /// The time from spawning the task until its first output, see
+    /// `--profile-spawn`.
+    spawn_latency: Option<Duration>,
+    /// Whether the task was killed through the control file.
+    cancelled: bool,
+    /// Whether the task was killed by `--timeout` or `--timeout-factor`.
+    timed_out: Option<Kill>,
+    program: String,
+    /// The start time as a unix timestamp in seconds.
+    started: f64,
+    /// The signal that killed the task, if any.
+    signal: Option<i32>,
+    /// Marks a warmup, which is written like any other result but left
+    /// out of the metric and spawn statistics of the summary.
+    warmup: bool,
+    /// Marks a placeholder of `--simulate`, the task did not run.
+    simulated: bool,
+    /// The `retry_with` override of the last attempt, 0 for the original
+    /// arguments. All variants failed if this is the last override and
+    /// the task failed.
+    variant: usize,
+    /// The hash of stdout, see `--verify-deterministic`.
+    stdout_hash: Option<u64>,
+    /// The stdout of the task, if kept by `Task::keep_output_if`.
+    output: Option<String>,
+    /// The round of the task with `--repeat`, counting from 0.
+    repeat: Option<usize>,
+    /// See `Cmd::index`.
+    index: usize,
+    /// Why the task could not be started, e.g. since its program is
+    /// missing. Such tasks have failed without an exit code.
+    error: Option<String>,
+}
+
+impl TaskResult {
+    /// The result of a task that did not run, which took no time at all
+    /// and has neither an exit code nor any output or metrics.
+    fn unstarted(
+        name: String,
+        task: String,
+        args: Vec<String>,
+        dims: Vec<(String, String)>,
+        program: String,
+    ) -> TaskResult {
+        TaskResult {
+            name,
+            task,
+            args,
+            dims,
+            duration: Duration::ZERO,
+            exit_code: None,
+            metric: None,
+            usage: None,
+            metrics: Vec::new(),
+            unmatched_metrics: Vec::new(),
+            peak_rss: None,
+            timestamp: unix_now(),
+            spawn_latency: None,
+            cancelled: false,
+            timed_out: None,
+            program,
+            started: unix_now() as f64,
+            signal: None,
+            warmup: false,
+            simulated: false,
+            variant: 0,
+            stdout_hash: None,
+            output: None,
+            repeat: None,
+            index: 0,
+            error: None,
+        }
+    }
+
+    /// The placeholder result of `--simulate`: a successful exit after no
+    /// time at all.
+    fn simulated(cmd: Cmd) -> TaskResult {
+        TaskResult {
+            exit_code: Some(0),
+            simulated: true,
+            repeat: cmd.repeat,
+            index: cmd.index,
+            ..TaskResult::unstarted(cmd.name, cmd.task, cmd.args, cmd.dims, cmd.program)
+        }
+    }
+
+    /// The record of this result in the structured output formats.
+    fn to_json(&self) -> Json {
+        let record = Json::obj()
+            .with("name", self.name.as_str())
+            .with("task", self.task.as_str())
+            .with("args", self.args_json())
+            .with("dims", self.dims_json())
+            .with("duration", self.duration.as_secs_f64())
+            .with("exit_code", self.exit_code)
+            .with("metric", self.metric.clone())
+            .with("metrics", self.metrics_json())
+            .with("unmatched_metrics", self.unmatched_metrics.clone())
+            .with("peak_rss_kb", self.peak_rss)
+            .with("timestamp", self.timestamp)
+            .with("spawn_latency", self.spawn_latency.map(|l| l.as_secs_f64()))
+            .with("cancelled", self.cancelled)
+            .with(
+                "timed_out",
+                self.timed_out.map(|kill| match kill {
+                    Kill::AdaptiveTimeout => "adaptive",
+                    _ => "fixed",
+                }),
+            )
+            .with("warmup", self.warmup)
+            .with("simulated", self.simulated)
+            .with("variant", self.variant)
+            .with("repeat", self.repeat)
+            .with(
+                "stdout_hash",
+                self.stdout_hash.map(|h| format!("{:016x}", h)),
+            )
+            .with("output", self.output.clone())
+            .with("error", self.error.clone())
+            .with("host", hostname());
+        match self.usage.and_then(|u| u.io) {
+            Some((read, written)) => record.with("read_bytes", read).with("write_bytes", written),
+            None => record,
+        }
+    }
+
+    /// The record of `--format joblog`, in the columns of the job log of
+    /// GNU parallel. `Seq` is the position in the plan, `${index}` counting
+    /// from 1, so it stays the same across runs and the rounds of
+    /// `--repeat`. `Host` is always `:`, the local machine. `Send` and
+    /// `Receive` are always 0, since nothing is transferred. `Exitval` is
+    /// -1 for tasks killed by a signal, which is given in `Signal`.
+    fn to_joblog(&self) -> String {
+        let mut command = vec![shell_quote(&self.program)];
+        command.extend(self.args.iter().map(|a| shell_quote(a)));
+        format!(
+            "{}\t:\t{:14.3}\t{:10.3}\t0\t0\t{}\t{}\t{}",
+            self.index + 1,
+            self.started,
+            self.duration.as_secs_f64(),
+            self.exit_code.unwrap_or(-1),
+            self.signal.unwrap_or(0),
+            command.join(" ")
+        )
+    }
+
+    /// The record of `--format text`: `name[, repeat], duration[, metric]`,
+    /// with the round only given with `--repeat`.
+    fn to_text(&self) -> String {
+        let mut fields = vec![self.name.clone()];
+        fields.extend(self.repeat.map(|repeat| repeat.to_string()));
+        fields.push(self.duration.as_secs_f64().to_string());
+        fields.extend(self.metric.clone());
+        fields.join(", ")
+    }
+
+    /// The record of `--format json`, with the provenance of the run.
+    fn record_json(&self, git: Option<&GitHead>) -> Json {
+        let record = self.to_json();
+        match git {
+            Some(git) => record.with("git", git.to_json()),
+            None => record,
+        }
+    }
+
+    /// The captured metrics, as numbers where they parse as such.
+    fn metrics_json(&self) -> Json {
+        self.metrics.iter().fold(Json::obj(), |json, (k, v)| {
+            let value = v
+                .parse::<f64>()
+                .map(Json::Num)
+                .unwrap_or(Json::from(v.as_str()));
+            json.with(k, value)
+        })
+    }
+
+    fn args_json(&self) -> Json {
+        Json::from(self.args.clone())
+    }
+
+    fn dims_json(&self) -> Json {
+        pairs_json(&self.dims)
+    }
+}
+
+/// The header of `--format joblog`.
+const JOBLOG_HEADER: &str =
+    "Seq\tHost\tStarttime\tJobRuntime\tSend\tReceive\tExitval\tSignal\tCommand";
+
+/// Quotes an argument for the shell, if it contains anything but plain
+/// characters.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The name of this machine, so results pooled from several machines can
+/// be told apart. Read once, and "unknown" if it cannot be read.
+fn hostname() -> &'static str {
+    static HOSTNAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    HOSTNAME.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            let mut buf = [0u8; 256];
+            // SAFETY: the buffer is valid for its length, which leaves room
+            // for the terminating nul
+            let r = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) };
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(0);
+            if r == 0 && len > 0 {
+                return String::from_utf8_lossy(&buf[..len]).into_owned();
+            }
+        }
+        "unknown".to_string()
+    })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The destination of the per-task records.
+///
+/// In split mode every top-level task gets its own file `<dir>/<task>.txt`,
+/// which is opened lazily once its first record arrives. Task names are
+/// sanitized for use as a filename, so two distinct task names could map
+/// onto the same file. This is rejected upfront by `Output::split`.
+enum Output {
+    None,
+    File(BufWriter<File>),
+    Split {
+        dir: PathBuf,
+        files: HashMap<String, BufWriter<File>>,
+    },
+}
+
+impl Output {
+    fn open(path: &Path) -> std::io::Result<BufWriter<File>> {
+        let f = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(BufWriter::new(f))
+    }
+
+    /// Writes to stdout, through a duplicate of its file descriptor.
+    #[cfg(unix)]
+    fn stdout() -> std::io::Result<BufWriter<File>> {
+        use std::os::fd::AsFd;
+        let fd = std::io::stdout().as_fd().try_clone_to_owned()?;
+        Ok(BufWriter::new(File::from(fd)))
+    }
+
+    #[cfg(not(unix))]
+    fn stdout() -> std::io::Result<BufWriter<File>> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "only supported on Unix",
+        ))
+    }
+
+    fn split<'a>(dir: PathBuf, tasks: impl Iterator<Item = &'a String>) -> Result<Output, String> {
+        let mut seen: HashMap<String, &String> = HashMap::new();
+        for task in tasks {
+            if let Some(other) = seen.insert(sanitize_file_name(task), task) {
+                return Err(format!(
+                    "Tasks '{}' and '{}' would share the output file '{}.txt'",
+                    other,
+                    task,
+                    sanitize_file_name(task)
+                ));
+            }
+        }
+
+        Ok(Output::Split {
+            dir,
+            files: HashMap::new(),
+        })
+    }
+
+    fn writer(&mut self, task: &str) -> std::io::Result<Option<&mut BufWriter<File>>> {
+        match self {
+            Output::None => Ok(None),
+            Output::File(f) => Ok(Some(f)),
+            Output::Split { dir, files } => {
+                if !files.contains_key(task) {
+                    let path = dir.join(format!("{}.txt", sanitize_file_name(task)));
+                    files.insert(task.to_string(), Output::open(&path)?);
+                }
+                Ok(files.get_mut(task))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::None => Ok(()),
+            Output::File(f) => f.flush(),
+            Output::Split { files, .. } => files.values_mut().try_for_each(|f| f.flush()),
+        }
+    }
+}
+
+/// The number of hex digits of the hash that ends truncated names.
+const NAME_HASH_LEN: usize = 8;
+
+/// Parses `--max-name-length`, which must leave room for the hash.
+fn parse_max_name_length(s: &str) -> Result<usize, String> {
+    let max = s.parse::<usize>().map_err(|e| e.to_string())?;
+    if max < NAME_HASH_LEN {
+        return Err(format!(
+            "must be at least {}, the length of the hash of truncated names",
+            NAME_HASH_LEN
+        ));
+    }
+    Ok(max)
+}
+
+/// Truncates a task name to at most `max` characters. Truncated names end
+/// in `-` followed by 8 hex digits of a hash of the full name, so distinct
+/// names stay distinct. If there is no room for a prefix, the name is just
+/// the hash. The hash is FNV-1a, which is stable across builds and thus
+/// across runs. The full name is lost, since static arguments and presets
+/// have no dimension, but the task and arguments of the structured outputs
+/// still tell the tasks apart.
+fn shorten_name(name: &str, max: usize) -> String {
+    if name.chars().count() <= max {
+        return name.to_string();
+    }
+
+    let hash = fnv1a(name.as_bytes());
+    let hash = format!(
+        "{:0width$x}",
+        (hash >> 32) ^ (hash & 0xffff_ffff),
+        width = NAME_HASH_LEN
+    );
+
+    let keep = max.saturating_sub(hash.len() + 1);
+    if keep == 0 {
+        return hash;
+    }
+    let prefix = name.chars().take(keep).collect::<String>();
+    format!("{}-{}", prefix, hash)
+}
+
+/// The 64-bit FNV-1a hash.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Writes the reproduction script of a failed task, see `Cmd::repro_script`.
+fn write_repro_script(dir: &Path, name: &str, script: &str) -> std::io::Result<()> {
+    let file_name = format!("repro_{}.sh", shorten_name(&sanitize_file_name(name), 200));
+    let path = dir.join(file_name);
+    std::fs::write(&path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Captures the stdout of every task in a file of its own, named after
+/// the task.
+///
+/// With a budget the total size of all logs is capped. Once the budget is
+/// used up, the log that exceeded it is cut off at the budget and no more
+/// output is captured, while the existing logs are kept as they are.
+struct LogDir {
+    dir: PathBuf,
+    budget: Option<u64>,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl LogDir {
+    fn create(dir: PathBuf, budget: Option<u64>) -> std::io::Result<LogDir> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(LogDir {
+            dir,
+            budget,
+            used: AtomicU64::new(0),
+            exhausted: AtomicBool::new(false),
+        })
+    }
+
+    fn write(&self, name: &str, stdout: &[u8]) -> std::io::Result<()> {
+        let len = stdout.len() as u64;
+        let granted = match self.budget {
+            None => len,
+            Some(budget) => {
+                let prev = self
+                    .used
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                        Some((used + len).min(budget))
+                    })
+                    .unwrap();
+                (budget - prev).min(len)
+            }
+        };
+        if granted < len && !self.exhausted.swap(true, Ordering::SeqCst) {
+            eprintln!(
+                "\n[PRUN] Warning: the logs reached the --log-budget of {} bytes, no more output is captured",
+                self.budget.unwrap_or_default()
+            );
+        }
+        if granted == 0 && len > 0 {
+            return Ok(());
+        }
+
+        let file_name = format!("{}.log", shorten_name(&sanitize_file_name(name), 200));
+        std::fs::write(self.dir.join(file_name), &stdout[..granted as usize])
+    }
+}
+
+/// Hands out the queued tasks to the workers, holding back tasks until the
+/// tasks they depend on have completed. Dependencies are between top-level
+/// tasks, so all concrete tasks of a dependency must have completed.
+/// Barriers are completed right here once they become ready, without ever
+/// reaching a worker. Dependencies that are not part of the queue at all
+/// (e.g. removed by `--filter`) count as completed. Of a serial task,
+/// only one concrete task is handed out at a time.
+///
+/// With `--repeat`, the tasks of a round are only handed out once all
+/// tasks of the previous rounds have completed, and dependencies are
+/// between the tasks of the same round.
+struct Scheduler {
+    state: Mutex<SchedulerState>,
+    changed: Condvar,
+    workers: usize,
+}
+
+struct SchedulerState {
+    queue: VecDeque<Cmd>,
+    /// The number of queued or running concrete tasks per top-level task
+    /// and round.
+    pending: HashMap<String, Vec<usize>>,
+    /// The number of queued or running tasks per round.
+    rounds: Vec<usize>,
+    barriers: usize,
+    /// The serial top-level tasks with a concrete task handed out.
+    serial: HashSet<String>,
+    /// The captured stdout per top-level task and round, by the position
+    /// of the concrete task, see `Task::capture_output`.
+    outputs: HashMap<(String, usize), BTreeMap<usize, String>>,
+}
+
+impl SchedulerState {
+    fn is_ready(&self, cmd: &Cmd) -> bool {
+        let round = cmd.repeat.unwrap_or(0);
+        self.rounds[..round].iter().all(|pending| *pending == 0)
+            && !(cmd.serial && self.serial.contains(&cmd.task))
+            && cmd.depends_on.iter().all(|dep| {
+                let pending = self.pending.get(dep).and_then(|rounds| rounds.get(round));
+                pending.copied().unwrap_or(0) == 0
+            })
+    }
+
+    fn finish(&mut self, task: &str, round: usize) {
+        if let Some(pending) = self.pending.get_mut(task) {
+            pending[round] -= 1;
+        }
+        self.rounds[round] -= 1;
+        self.serial.remove(task);
+    }
+
+    /// Fills in the outputs of the dependencies of a ready task. Tasks
+    /// that did not run, e.g. skipped by `--resume`, have no output, so
+    /// their placeholders are kept.
+    fn interpolate(&self, cmd: &mut Cmd) {
+        for dep in cmd.depends_on.clone() {
+            if let Some(outputs) = self.outputs.get(&(dep.clone(), cmd.repeat.unwrap_or(0))) {
+                let output = outputs.values().cloned().collect::<Vec<_>>().join(" ");
+                cmd.substitute(&format!("${{{}.output}}", dep), &output);
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    fn new(mut queue: VecDeque<Cmd>, workers: usize) -> Scheduler {
+        for (position, cmd) in queue.iter_mut().enumerate() {
+            cmd.position = position;
+        }
+        let count = queue
+            .iter()
+            .map(|cmd| cmd.repeat.unwrap_or(0) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut pending: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut rounds = vec![0; count];
+        for cmd in &queue {
+            let round = cmd.repeat.unwrap_or(0);
+            pending
+                .entry(cmd.task.clone())
+                .or_insert_with(|| vec![0; count])[round] += 1;
+            rounds[round] += 1;
+        }
+        let barriers = queue.iter().filter(|cmd| cmd.barrier).count();
+        Scheduler {
+            state: Mutex::new(SchedulerState {
+                queue,
+                pending,
+                rounds,
+                barriers,
+                serial: HashSet::new(),
+                outputs: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+            workers: workers.max(1),
+        }
+    }
+
+    /// Takes up to `max` ready tasks, waiting for running tasks to complete
+    /// if none is ready. Returns no tasks once the queue is empty.
+    ///
+    /// Tasks of the group of the worker are preferred. Only if none of
+    /// them is ready, the worker helps out with the tasks of other groups.
+    ///
+    /// Fewer tasks are handed out as the queue runs low, so that no worker
+    /// holds on to tasks while the others are idle: every worker gets at
+    /// most its share of the remaining tasks.
+    fn next(&self, group: Option<&str>, max: usize) -> Vec<Cmd> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            while state.barriers > 0 {
+                let Some(idx) = state
+                    .queue
+                    .iter()
+                    .position(|cmd| cmd.barrier && state.is_ready(cmd))
+                else {
+                    break;
+                };
+                let barrier = state.queue.remove(idx).unwrap();
+                state.barriers -= 1;
+                state.finish(&barrier.task, barrier.repeat.unwrap_or(0));
+                self.changed.notify_all();
+            }
+
+            let limit = max.min(state.queue.len() / self.workers).max(1);
+            let mut batch = Vec::new();
+            while batch.len() < limit {
+                let ready = |cmd: &Cmd| !cmd.barrier && state.is_ready(cmd);
+                let Some(idx) = state
+                    .queue
+                    .iter()
+                    .position(|cmd| ready(cmd) && cmd.group.as_deref() == group)
+                    .or_else(|| state.queue.iter().position(ready))
+                else {
+                    break;
+                };
+                let mut cmd = state.queue.remove(idx).unwrap();
+                if cmd.serial {
+                    state.serial.insert(cmd.task.clone());
+                }
+                state.interpolate(&mut cmd);
+                batch.push(cmd);
+            }
+            if !batch.is_empty() || state.queue.is_empty() {
+                return batch;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Marks a task of the top-level task and round as completed, with
+    /// its position and stdout if captured.
+    fn complete(&self, task: &str, repeat: Option<usize>, output: Option<(usize, String)>) {
+        let mut state = self.state.lock().unwrap();
+        let round = repeat.unwrap_or(0);
+        if let Some((position, output)) = output {
+            state
+                .outputs
+                .entry((task.to_string(), round))
+                .or_default()
+                .insert(position, output);
+        }
+        state.finish(task, round);
+        self.changed.notify_all();
+    }
+}
+
+/// Assigns each of the `n` workers to a group, in proportion to the
+/// weights of the groups with tasks in the queue. Tasks without a group
+/// form a group of their own with weight 1. Shares are rounded with the
+/// largest remainder method, so all workers are assigned.
+fn allocate_workers(queue: &VecDeque<Cmd>, n: usize) -> Vec<Option<String>> {
+    let mut groups: Vec<(Option<&str>, u32)> = Vec::new();
+    for cmd in queue.iter().filter(|cmd| !cmd.barrier) {
+        if !groups.iter().any(|(g, _)| *g == cmd.group.as_deref()) {
+            groups.push((cmd.group.as_deref(), cmd.weight));
+        }
+    }
+    let total = groups
+        .iter()
+        .map(|(_, w)| *w as usize)
+        .sum::<usize>()
+        .max(1);
+
+    let mut shares = groups
+        .iter()
+        .map(|(_, w)| (n * *w as usize / total, n * *w as usize % total))
+        .collect::<Vec<_>>();
+    let assigned = shares.iter().map(|(s, _)| s).sum::<usize>();
+    let mut order = (0..shares.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(shares[i].1));
+    for &i in order.iter().take(n - assigned) {
+        shares[i].0 += 1;
+    }
+
+    groups
+        .iter()
+        .zip(shares)
+        .flat_map(|((group, _), (share, _))| std::iter::repeat_n(group.map(str::to_string), share))
+        .collect()
+}
+
+/// Checks that the groups of the tasks are declared and have a weight.
+fn check_groups(config: &Config) -> Result<(), String> {
+    if let Some((name, _)) = config.groups.iter().find(|(_, g)| g.weight == 0) {
+        return Err(format!("Group '{}' must have a weight of at least 1", name));
+    }
+    for (name, task) in &config.tasks {
+        if let Some(group) = task
+            .group
+            .as_ref()
+            .filter(|g| !config.groups.contains_key(*g))
+        {
+            return Err(format!("Task '{}' is in unknown group '{}'", name, group));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the dependencies between the tasks can be satisfied: every
+/// dependency exists, there are no cycles, and exactly the barriers have
+/// no command.
+fn check_dependencies(tasks: &Tasks) -> Result<(), String> {
+    for (name, task) in tasks {
+        if task.barrier
+            && (!task.command.is_empty() || !task.args.is_empty() || task.generator.is_some())
+        {
+            return Err(format!(
+                "Barrier '{}' must not have a command, args or a generator",
+                name
+            ));
+        }
+        if !task.barrier && task.command.is_empty() {
+            return Err(format!("Task '{}' has no command", name));
+        }
+        if let Some(dep) = task.depends_on.iter().find(|d| !tasks.contains_key(*d)) {
+            return Err(format!("Task '{}' depends on unknown task '{}'", name, dep));
+        }
+        check_output_refs(name, task, tasks)?;
+    }
+
+    // Depth-first search for back edges, `true` marking tasks on the stack
+    fn visit<'a>(
+        tasks: &'a Tasks,
+        name: &'a str,
+        state: &mut HashMap<&'a str, bool>,
+    ) -> Result<(), String> {
+        match state.get(name) {
+            Some(true) => return Err(format!("Tasks depend on each other through '{}'", name)),
+            Some(false) => return Ok(()),
+            None => {}
+        }
+        state.insert(name, true);
+        for dep in &tasks[name].depends_on {
+            visit(tasks, dep, state)?;
+        }
+        state.insert(name, false);
+        Ok(())
+    }
+
+    let mut state = HashMap::new();
+    for name in tasks.keys() {
+        visit(tasks, name, &mut state)?;
+    }
+    Ok(())
+}
+
+/// Limits how many tasks may be started before the workers have to wait
+/// for the run to be resumed. Workers take a permit after popping a task,
+/// so that workers finding the queue empty never wait for one, and report
+/// back once that task has completed.
+struct Gate {
+    state: Mutex<GateState>,
+    changed: Condvar,
+}
+
+struct GateState {
+    budget: usize,
+    completed: usize,
+}
+
+impl Gate {
+    fn new(budget: usize) -> Gate {
+        Gate {
+            state: Mutex::new(GateState {
+                budget,
+                completed: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.budget == 0 {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.budget -= 1;
+    }
+
+    fn complete(&self) {
+        self.state.lock().unwrap().completed += 1;
+        self.changed.notify_all();
+    }
+
+    fn wait_completed(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        while state.completed < n {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn resume(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.budget = state.budget.saturating_add(n);
+        self.changed.notify_all();
+    }
+
+    /// Pauses the run after every `step` of the `total` tasks until
+    /// `confirm` returns, with the number of completed tasks. If it
+    /// returns false, the rest of the run continues without pausing.
+    fn pause_every(&self, step: usize, total: usize, mut confirm: impl FnMut(usize) -> bool) {
+        let mut target = step;
+        while target < total {
+            self.wait_completed(target);
+            if !confirm(target) {
+                self.resume(usize::MAX);
+                break;
+            }
+            self.resume(step);
+            target += step;
+        }
+    }
+}
+
+/// Falls back to a comma separated list from the environment variable
+/// `var` if the corresponding flag was not given. Flags always take
+/// precedence, so CI matrix cells can select tasks through the environment
+/// while a local invocation can still override them.
+fn from_env_if_empty(values: &mut Vec<String>, var: &str) {
+    if values.is_empty() {
+        if let Ok(env) = std::env::var(var) {
+            values.extend(env.split(',').filter(|v| !v.is_empty()).map(str::to_string));
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Result<Config, String> {
+    if !path.exists() {
+        return Err(format!("Could not find config file '{:?}'", path));
+    }
+
+    let mut file = match File::open(path) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => return Err(format!("Could not open config file '{:?}': {}", path, e)),
+    };
+
+    let mut string = String::new();
+    if let Err(e) = file.read_to_string(&mut string) {
+        return Err(format!("Failed to read config file '{:?}': {}", path, e));
+    }
+
+    let config = toml::from_str::<Config>(&string)
+        .map_err(|e| format!("Failed to parse config file'{:?}': {}", path, e))?;
+    check_dependencies(&config.tasks)?;
+    check_groups(&config)?;
+    Ok(config)
+}
+
+/// Checks that the `${<task>.output}` placeholders in the arguments of a
+/// task refer to dependencies that capture their output.
+fn check_output_refs(name: &str, task: &Task, tasks: &Tasks) -> Result<(), String> {
+    let args = task.args.iter().flat_map(|arg| match arg {
+        Argument::Static(s) => std::slice::from_ref(s),
+        Argument::Choice(choices) => &choices[..],
+        _ => &[],
+    });
+    for arg in args.chain(task.retry_with.iter().flatten()) {
+        let mut rest = arg.as_str();
+        while let Some(start) = rest.find("${") {
+            rest = &rest[start + 2..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            if let Some(dep) = rest[..end].strip_suffix(".output") {
+                if !task.depends_on.iter().any(|d| d == dep) {
+                    return Err(format!(
+                        "Task '{}' refers to the output of '{}', but does not depend on it",
+                        name, dep
+                    ));
+                }
+                if !tasks[dep].capture_output {
+                    return Err(format!(
+                        "Task '{}' refers to the output of '{}', which does not set capture_output",
+                        name, dep
+                    ));
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    Ok(())
+}
+
+/// The format of the per-task records in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// `name, duration[, last line of stdout]`
+    Text,
+    /// One JSON object per line, see `TaskResult::to_json`.
+    Json,
+    /// A single JSON object for the whole run, see `RunDocument`.
+    RunJson,
+    /// The tab-separated job log of GNU parallel, see `TaskResult::to_joblog`.
+    Joblog,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "run-json" => Ok(Format::RunJson),
+            "joblog" => Ok(Format::Joblog),
+            _ => Err(format!(
+                "unknown format '{}', expected 'text', 'json', 'run-json' or 'joblog'",
+                s
+            )),
+        }
+    }
+}
+
+/// When to color the output on a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    /// Only if the output goes to a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color the informational output, which goes to stdout or
+    /// stderr, see `info!`.
+    fn enabled(self) -> bool {
+        let terminal = if INFO_ON_STDERR.load(Ordering::Relaxed) {
+            std::io::stderr().is_terminal()
+        } else {
+            std::io::stdout().is_terminal()
+        };
+        self.enabled_on(terminal)
+    }
+
+    /// Whether to color the output on a stream, given whether it is a
+    /// terminal.
+    fn enabled_on(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice '{}', expected 'auto', 'always' or 'never'",
+                s
+            )),
+        }
+    }
+}
+
+/// Wraps the text in an ANSI color escape, if colors are enabled.
+fn paint(text: &str, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Counts a completed task and shows the new count on the progress line,
+/// in red once a task failed.
+fn print_progress(done: &AtomicUsize, total: usize, failed: bool, color: bool) {
+    let v = done.fetch_add(1, Ordering::SeqCst);
+    let status = if failed { RED } else { GREEN };
+    let count = format!("{}/{}", v + 1, total);
+    info!("\rProgress [{}]", paint(&count, status, color));
+}
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+
+/// What the workers do if the result queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueuePolicy {
+    /// Wait until the writer has caught up. No result is lost, but a
+    /// stalled output blocks all workers.
+    Block,
+    /// Drop the oldest queued result and warn about it. Workers are never
+    /// stalled, but the dropped records are missing from all outputs.
+    DropOldest,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(QueuePolicy::Block),
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            _ => Err(format!(
+                "unknown queue policy '{}', expected 'block' or 'drop-oldest'",
+                s
+            )),
+        }
+    }
+}
+
+/// A bounded queue carrying the results from the workers to the writer
+/// thread.
+struct ResultQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    capacity: usize,
+    policy: QueuePolicy,
+}
+
+struct QueueState {
+    results: VecDeque<TaskResult>,
+    closed: bool,
+    dropped: usize,
+}
+
+impl ResultQueue {
+    fn new(capacity: usize, policy: QueuePolicy) -> ResultQueue {
+        ResultQueue {
+            state: Mutex::new(QueueState {
+                results: VecDeque::new(),
+                closed: false,
+                dropped: 0,
+            }),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    fn send(&self, result: TaskResult) {
+        let mut state = self.state.lock().unwrap();
+        while state.results.len() >= self.capacity {
+            match self.policy {
+                QueuePolicy::Block => state = self.changed.wait(state).unwrap(),
+                QueuePolicy::DropOldest => {
+                    let dropped = state.results.pop_front().unwrap();
+                    state.dropped += 1;
+                    eprintln!(
+                        "\n[PRUN] Warning: output is falling behind, dropped the result of {:?}",
+                        dropped.name
+                    );
+                }
+            }
+        }
+        state.results.push_back(result);
+        self.changed.notify_all();
+    }
+
+    /// Takes all queued results, waiting for at least one. Returns `None`
+    /// once the queue is closed and drained.
+    fn recv_batch(&self) -> Option<Vec<TaskResult>> {
+        let mut state = self.state.lock().unwrap();
+        while state.results.is_empty() {
+            if state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+        let batch = state.results.drain(..).collect();
+        self.changed.notify_all();
+        Some(batch)
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    fn dropped(&self) -> usize {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/// The output of `--format run-json`: a single object with the `meta`
+/// data of the run, the `plan` and the `results`, written once the run
+/// has finished. Until then all records are kept in memory, which for
+/// runs of millions of tasks can take gigabytes, unlike the streaming
+/// formats. The document is written on a single line, so appending runs
+/// to the same file yields one document per line.
+struct RunDocument {
+    meta: Json,
+    plan: Json,
+    results: Vec<Json>,
+}
+
+/// Everything the writer thread writes the results to.
+struct Sinks {
+    output: Output,
+    /// Collects the records instead of `output` with `--format run-json`.
+    document: Option<RunDocument>,
+    db: Option<Db>,
+    target: Option<LogTarget>,
+    /// Receives the names of the successful tasks, for `--resume`.
+    checkpoint: Option<File>,
+    sink: Option<ResultSink>,
+    board: Option<Arc<Board>>,
+    textfile: Option<Textfile>,
+}
+
+/// Opens the checkpoint file, which is continued when resuming and
+/// started over otherwise.
+fn open_checkpoint(path: &Path, resume: bool) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resume)
+        .truncate(!resume)
+        .open(path)
+}
+
+/// Reads the names of the tasks recorded in a checkpoint file. A missing
+/// file is an empty checkpoint, so `--resume` also works on the first
+/// run. Every line is a JSON string, so names may contain line breaks.
+/// A line cut off by an interrupted write is ignored.
+fn read_checkpoint(path: &Path) -> std::io::Result<HashSet<String>> {
+    let string = match std::fs::read_to_string(path) {
+        Ok(string) => string,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(string
+        .lines()
+        .filter_map(|line| match Json::parse(line) {
+            Ok(Json::Str(name)) => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Receives the results from the workers and writes them to the output
+/// and the results database. Results that arrived while the previous batch
+/// was written are written together as the next batch. The output is
+/// flushed after every batch, or after every record if `unbuffered`.
+///
+/// The checkpoint is synced after every batch, and only after the batch
+/// has been written everywhere else, so a task in the checkpoint is never
+/// missing from the other outputs.
+fn write_results(
+    queue: &ResultQueue,
+    sinks: Sinks,
+    format: Format,
+    unbuffered: bool,
+    git: Option<&GitHead>,
+    summary: Summary,
+) -> (Summary, std::io::Result<()>) {
+    let Sinks {
+        mut output,
+        mut document,
+        mut db,
+        target,
+        mut checkpoint,
+        mut sink,
+        board,
+        mut textfile,
+    } = sinks;
+    let mut summary = summary;
+    let mut headers = HashSet::new();
+    // After the first error the records are discarded, so that the workers
+    // are not blocked by a full queue
+    let mut failure = None;
+    while let Some(batch) = queue.recv_batch() {
+        for result in &batch {
+            summary.add(result);
+            if let Some(board) = &board {
+                board.finish(result);
+            }
+            if let Some(textfile) = textfile.as_mut() {
+                textfile.add(result);
+            }
+            if let Some(document) = document.as_mut() {
+                document.results.push(result.to_json());
+            } else if failure.is_none() {
+                let written = write_record(&mut output, result, format, &mut headers, git);
+                let written = written.and_then(|_| match unbuffered {
+                    true => output.flush(),
+                    false => Ok(()),
+                });
+                keep_write_error(&mut failure, written);
+            }
+            if let Some(target) = &target {
+                if let Err(e) = target.send(&result.to_text(), &result.record_json(git)) {
+                    eprintln!("[PRUN] Failed to write to the system log: {}", e);
+                }
+            }
+            if let Some(sink) = sink.as_mut() {
+                sink.send(&result.record_json(git));
+                if unbuffered {
+                    sink.flush();
+                }
+            }
+        }
+        if failure.is_none() {
+            keep_write_error(&mut failure, output.flush());
+        }
+        if let Some(sink) = sink.as_mut() {
+            sink.flush();
+        }
+
+        if let Some(db) = db.as_mut() {
+            if let Err(e) = db.insert(&batch) {
+                eprintln!("[PRUN] Failed to write to the results database: {}", e);
+            }
+        }
+
+        if let Some(file) = checkpoint.as_mut() {
+            let mut names = String::new();
+            for result in batch.iter().filter(|r| r.exit_code == Some(0)) {
+                names.push_str(&Json::from(result.name.as_str()).to_string());
+                names.push('\n');
+            }
+            if let Err(e) = file
+                .write_all(names.as_bytes())
+                .and_then(|_| file.sync_data())
+            {
+                eprintln!("[PRUN] Failed to write to the checkpoint: {}", e);
+            }
+        }
+    }
+
+    if let Some(RunDocument {
+        meta,
+        plan,
+        results,
+    }) = document
+    {
+        let document = Json::obj()
+            .with("meta", meta.with("finished", unix_now()))
+            .with("plan", plan)
+            .with("results", results);
+        let written = output.writer("").and_then(|writer| match writer {
+            Some(writer) => writeln!(writer, "{}", document),
+            None => Ok(()),
+        });
+        keep_write_error(&mut failure, written.and_then(|_| output.flush()));
+    }
+
+    if let Some(db) = db {
+        if let Err(e) = db.close() {
+            eprintln!("[PRUN] Failed to close the results database: {}", e);
+        }
+    }
+
+    if let Some(sink) = sink {
+        if let Err(e) = sink.close() {
+            eprintln!("[PRUN] The result sink failed: {}", e);
+        }
+    }
+
+    if let Some(textfile) = textfile {
+        if let Err(e) = textfile.write() {
+            eprintln!("[PRUN] Failed to write the Prometheus textfile: {}", e);
+        }
+    }
+
+    (summary, failure.map_or(Ok(()), Err))
+}
+
+/// Writes the record of a task to the output, with the header of a new
+/// joblog.
+fn write_record(
+    output: &mut Output,
+    result: &TaskResult,
+    format: Format,
+    headers: &mut HashSet<String>,
+    git: Option<&GitHead>,
+) -> std::io::Result<()> {
+    let split = matches!(output, Output::Split { .. });
+    let Some(output) = output.writer(&result.task)? else {
+        return Ok(());
+    };
+    match format {
+        Format::Text => writeln!(output, "{}", result.to_text()),
+        Format::Joblog => {
+            // Like GNU parallel, only new job logs start with a header
+            let key = if split { result.task.as_str() } else { "" };
+            if !headers.contains(key) {
+                headers.insert(key.to_string());
+                if output.get_ref().metadata().map(|m| m.len()).unwrap_or(0) == 0 {
+                    writeln!(output, "{}", JOBLOG_HEADER)?;
+                }
+            }
+            writeln!(output, "{}", result.to_joblog())
+        }
+        Format::Json | Format::RunJson => writeln!(output, "{}", result.record_json(git)),
+    }
+}
+
+/// Keeps the first error writing the records and reports it, the records
+/// after it are discarded.
+fn keep_write_error(failure: &mut Option<std::io::Error>, written: std::io::Result<()>) {
+    if let (None, Err(e)) = (&failure, written) {
+        eprintln!(
+            "\n[PRUN] Failed to write the records, discarding the rest: {}",
+            e
+        );
+        *failure = Some(e);
+    }
+}
+
+/// Resource usage of a reaped child, as reported by `wait4`.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    /// User plus system CPU time.
+    cpu_time: Duration,
+    /// When the first byte of stdout was read, if there was any output.
+    first_output: Option<Instant>,
+    /// The bytes read from and written to storage, from `/proc/<pid>/io`
+    /// (Linux only).
+    io: Option<(u64, u64)>,
+}
+
+/// Like `Child::wait_with_output`, but also reports the resource usage
+/// of the child. This reaps the child with `wait4`, so the usage belongs
+/// to exactly this child and not to the other running tasks.
+///
+/// `done` is called once the output has been read, but before the child
+/// is reaped, so its pid cannot have been reused yet while `done` runs.
+/// Both happen even if reading the output fails, in which case the child
+/// is killed first, so that no zombie is left behind.
+#[cfg(unix)]
+fn wait_with_usage(
+    mut child: Child,
+    done: impl FnOnce(),
+) -> std::io::Result<(process::Output, Option<Usage>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        })
+    });
+
+    let stdout = read_stdout(child.stdout.take());
+    if stdout.is_err() {
+        // Otherwise the child may block on the full pipe and never exit
+        let _ = child.kill();
+    }
+    let stderr = match stderr {
+        Some(handle) => handle.join().unwrap(),
+        None => Ok(Vec::new()),
+    };
+
+    // Wait for the exit without reaping, since `/proc/<pid>/io` is gone
+    // once the child is reaped
+    #[cfg(target_os = "linux")]
+    let io = {
+        // SAFETY: siginfo is plain old data, so zeroed memory is a valid value
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        loop {
+            // SAFETY: the pid belongs to an unreaped child of this process
+            let r = unsafe {
+                libc::waitid(
+                    libc::P_PID,
+                    child.id() as libc::id_t,
+                    &mut info,
+                    libc::WEXITED | libc::WNOWAIT,
+                )
+            };
+            if r == 0 || std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+                break;
+            }
+        }
+        read_io(child.id())
+    };
+    #[cfg(not(target_os = "linux"))]
+    let io = None;
+    done();
+
+    let mut status = 0;
+    // SAFETY: rusage is plain old data, so zeroed memory is a valid value
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pid belongs to an unreaped child of this process
+        let r = unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut rusage) };
+        if r >= 0 {
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    let (stdout, first_output) = stdout?;
+    let tv = |tv: libc::timeval| Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    let usage = Usage {
+        cpu_time: tv(rusage.ru_utime) + tv(rusage.ru_stime),
+        first_output,
+        io,
+    };
+
+    let output = process::Output {
+        status: ExitStatus::from_raw(status),
+        stdout,
+        stderr: stderr?,
+    };
+    Ok((output, Some(usage)))
+}
+
+/// Reads stdout of a child to the end, with the time the first byte was
+/// read.
+#[cfg(unix)]
+fn read_stdout(pipe: Option<process::ChildStdout>) -> std::io::Result<(Vec<u8>, Option<Instant>)> {
+    let mut stdout = Vec::new();
+    let mut first_output = None;
+    if let Some(mut pipe) = pipe {
+        let mut buf = [0; 4096];
+        let n = loop {
+            match pipe.read(&mut buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                read => break read?,
+            }
+        };
+        if n > 0 {
+            first_output = Some(Instant::now());
+            stdout.extend_from_slice(&buf[..n]);
+            pipe.read_to_end(&mut stdout)?;
+        }
+    }
+    Ok((stdout, first_output))
+}
+
+#[cfg(not(unix))]
+fn wait_with_usage(
+    child: Child,
+    done: impl FnOnce(),
+) -> std::io::Result<(process::Output, Option<Usage>)> {
+    let output = child.wait_with_output();
+    done();
+    output.map(|output| (output, None))
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Why a running task was killed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kill {
+    /// Through the control file.
+    Cancelled,
+    /// By `--timeout`.
+    Timeout,
+    /// By `--timeout-factor`.
+    AdaptiveTimeout,
+}
+
+/// The running tasks with their name, pid and start, so they can be
+/// cancelled through the control file or killed after a timeout. Tasks
+/// are registered under an id of their launch, since several tasks of
+/// the same name run at once with `--repeat`.
+#[derive(Default)]
+struct Running {
+    pids: Mutex<HashMap<usize, (String, u32, Instant)>>,
+    killed: Mutex<HashMap<usize, Kill>>,
+    next: AtomicUsize,
+    /// Held while a task is started, see `wait_for_memory`.
+    launch: Mutex<()>,
+}
+
+impl Running {
+    /// Registers a task, returning the id of its launch.
+    fn insert(&self, name: &str, pid: u32) -> usize {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        self.pids
+            .lock()
+            .unwrap()
+            .insert(id, (name.to_string(), pid, started));
+        id
+    }
+
+    /// Forgets a task, which must happen before its child is reaped.
+    fn remove(&self, id: usize) {
+        self.pids.lock().unwrap().remove(&id);
+    }
+
+    /// Why the task was killed, if it was, forgetting the reason.
+    fn take_killed(&self, id: usize) -> Option<Kill> {
+        self.killed.lock().unwrap().remove(&id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pids.lock().unwrap().is_empty()
+    }
+
+    /// The ids and names of the tasks running for longer than `limit`.
+    fn overdue(&self, limit: Duration) -> Vec<(usize, String)> {
+        let pids = self.pids.lock().unwrap();
+        pids.iter()
+            .filter(|(_, (_, _, started))| started.elapsed() > limit)
+            .map(|(id, (name, _, _))| (*id, name.clone()))
+            .collect()
+    }
+
+    /// Kills the child of a running task. Returns false if the task is no
+    /// longer running.
+    fn kill(&self, id: usize, reason: Kill) -> bool {
+        let pids = self.pids.lock().unwrap();
+        let Some(&(_, pid, _)) = pids.get(&id) else {
+            return false;
+        };
+        self.killed.lock().unwrap().insert(id, reason);
+        // SAFETY: the child is not reaped while it is registered, so the
+        // pid still belongs to it
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+        #[cfg(not(unix))]
+        let _ = pid;
+        true
+    }
+
+    /// Kills all running tasks of the name. Returns false if none runs.
+    fn cancel(&self, name: &str, reason: Kill) -> bool {
+        let pids = self.pids.lock().unwrap();
+        let ids: Vec<usize> = pids
+            .iter()
+            .filter(|(_, (running, _, _))| running == name)
+            .map(|(id, _)| *id)
+            .collect();
+        drop(pids);
+        let killed = ids.into_iter().filter(|id| self.kill(*id, reason)).count();
+        killed > 0
+    }
+}
+
+/// Watches the control file for commands until `stop` is set. Lines
+/// appended to the file are executed as they appear, the supported command
+/// being `kill <task name>`. If the file shrinks, it is read from the
+/// start again.
+fn watch_control_file(path: &Path, running: &Running, stop: &AtomicBool) {
+    let mut offset = 0;
+    let mut pending = String::new();
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(200));
+
+        let Ok(content) = std::fs::read(path) else {
+            continue;
+        };
+        if content.len() < offset {
+            offset = 0;
+            pending.clear();
+        }
+        pending.push_str(&String::from_utf8_lossy(&content[offset..]));
+        offset = content.len();
+
+        while let Some(end) = pending.find('\n') {
+            let line = pending[..end].trim().to_string();
+            pending.drain(..=end);
+            match line.split_once(' ') {
+                _ if line.is_empty() => {}
+                Some(("kill", name)) => {
+                    if running.cancel(name.trim(), Kill::Cancelled) {
+                        eprintln!("\n[PRUN] Cancelled task {:?}", name.trim());
+                    } else {
+                        eprintln!(
+                            "\n[PRUN] Cannot cancel {:?}, it is not running",
+                            name.trim()
+                        );
+                    }
+                }
+                _ => eprintln!("\n[PRUN] Unknown control command {:?}", line),
+            }
+        }
+    }
+}
+
+/// The memory available for starting new tasks in bytes, i.e.
+/// `MemAvailable` from `/proc/meminfo`. `None` where it cannot be read.
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// Waits until at least `min` bytes of memory are available. Tasks are
+/// started regardless if no other task runs, since waiting would not free
+/// any memory then.
+///
+/// The returned guard must be held until the task is registered as
+/// running, so that workers start their tasks one by one, each seeing
+/// the tasks started before.
+fn wait_for_memory(min: u64, running: &Running) -> std::sync::MutexGuard<'_, ()> {
+    let launch = running.launch.lock().unwrap();
+    while available_memory().is_some_and(|available| available < min) && !running.is_empty() {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    launch
+}
+
+/// The number of completed tasks the median duration of `--timeout-factor`
+/// is based on at least.
+const MEDIAN_MIN_TASKS: usize = 5;
+
+/// The timeouts of a run. The adaptive timeout is a multiple of the median
+/// duration of the tasks completed so far, the fixed timeout applies until
+/// enough tasks have completed for a meaningful median.
+struct Timeouts {
+    fixed: Option<Duration>,
+    factor: Option<f64>,
+    durations: Mutex<Durations>,
+}
+
+/// The durations of the completed tasks in ascending order, with their
+/// median once there are enough of them. The median is updated as tasks
+/// complete, since the limit is checked far more often.
+#[derive(Default)]
+struct Durations {
+    sorted: Vec<Duration>,
+    median: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Records the duration of a task that completed on its own.
+    fn record(&self, duration: Duration) {
+        let mut durations = self.durations.lock().unwrap();
+        let idx = durations.sorted.partition_point(|d| *d <= duration);
+        durations.sorted.insert(idx, duration);
+        let len = durations.sorted.len();
+        if len >= MEDIAN_MIN_TASKS {
+            durations.median = Some(durations.sorted[len / 2]);
+        }
+    }
+
+    /// The current limit on the duration of tasks, and the kind of it.
+    fn limit(&self) -> Option<(Duration, Kill)> {
+        if let Some(factor) = self.factor {
+            if let Some(median) = self.durations.lock().unwrap().median {
+                return Some((median.mul_f64(factor), Kill::AdaptiveTimeout));
+            }
+        }
+        self.fixed.map(|fixed| (fixed, Kill::Timeout))
+    }
+}
+
+/// Kills the running tasks that exceed the timeout until `stop` is set.
+fn watch_timeouts(timeouts: &Timeouts, running: &Running, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(50));
+
+        let Some((limit, kind)) = timeouts.limit() else {
+            continue;
+        };
+        for (id, name) in running.overdue(limit) {
+            if running.kill(id, kind) {
+                let kind = match kind {
+                    Kill::AdaptiveTimeout => "the adaptive timeout",
+                    _ => "the timeout",
+                };
+                eprintln!(
+                    "\n[PRUN] Killed task {:?} after {} of {:?}",
+                    name, kind, limit
+                );
+            }
+        }
+    }
+}
+
+/// Reads the bytes a process read from and wrote to storage, i.e. the
+/// `read_bytes` and `write_bytes` of `/proc/<pid>/io`.
+#[cfg(target_os = "linux")]
+fn read_io(pid: u32) -> Option<(u64, u64)> {
+    let io = std::fs::read_to_string(format!("/proc/{}/io", pid)).ok()?;
+    let field = |name: &str| {
+        io.lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|v| v.trim().parse::<u64>().ok())
+    };
+    Some((field("read_bytes")?, field("write_bytes")?))
+}
+
+/// Samples the memory usage of a running child from `/proc/<pid>/status`
+/// until it is stopped, tracking the peak of `VmHWM` and `VmRSS` in KiB.
+/// Sampling also ends once the child exited, since zombies report no
+/// memory statistics.
+#[cfg(target_os = "linux")]
+struct MemorySampler {
+    stop: Arc<AtomicBool>,
+    handle: std::thread::JoinHandle<Option<u64>>,
+}
+
+#[cfg(target_os = "linux")]
+impl MemorySampler {
+    fn start(pid: u32, interval: Duration) -> MemorySampler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let path = format!("/proc/{}/status", pid);
+                let mut peak = None;
+                while !stop.load(Ordering::Relaxed) {
+                    let Ok(status) = std::fs::read_to_string(&path) else {
+                        break;
+                    };
+                    let sample = status
+                        .lines()
+                        .filter(|l| l.starts_with("VmHWM:") || l.starts_with("VmRSS:"))
+                        .filter_map(|l| l.split_whitespace().nth(1)?.parse::<u64>().ok())
+                        .max();
+                    match sample {
+                        Some(sample) => peak = peak.max(Some(sample)),
+                        None => break,
+                    }
+                    std::thread::sleep(interval);
+                }
+                peak
+            })
+        };
+        MemorySampler { stop, handle }
+    }
+
+    fn finish(self) -> Option<u64> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().unwrap()
+    }
+}
+
+/// Statistics over all completed tasks, collected by the writer thread.
+#[derive(Debug, Default)]
+struct Summary {
+    tasks: usize,
+    /// The summed CPU time of all tasks with known usage.
+    cpu_time: Duration,
+    /// The summed durations of all tasks, i.e. the time a serial run takes.
+    serial_time: Duration,
+    metrics: BTreeMap<String, MetricStats>,
+    /// The number of tasks with a spawn latency, the sum of their
+    /// latencies and the sum of their durations.
+    spawn: (usize, Duration, Duration),
+    warmups: usize,
+    /// The number of tasks with known disk IO, and their summed bytes read
+    /// and written.
+    io: (usize, u64, u64),
+    /// The distinct stdout hashes of every task, see `--verify-deterministic`.
+    hashes: BTreeMap<String, HashSet<u64>>,
+    /// The dimension of `--group-by`, and the metrics per value of it.
+    group_by: Option<String>,
+    grouped: BTreeMap<String, BTreeMap<String, MetricStats>>,
+    /// The thresholds of `--fail-under` and `--fail-over`, and the tasks
+    /// that violated them.
+    fail_under: Vec<Threshold>,
+    fail_over: Vec<Threshold>,
+    violations: Vec<String>,
+}
+
+/// A bound on a captured metric, see `--fail-under` and `--fail-over`.
+#[derive(Debug, Clone)]
+struct Threshold {
+    metric: String,
+    value: f64,
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected <metric>=<value>, got '{}'", s);
+        let (metric, value) = s.split_once('=').ok_or_else(invalid)?;
+        let value = value.trim().parse::<f64>().map_err(|_| invalid())?;
+        if metric.is_empty() || value.is_nan() {
+            return Err(invalid());
+        }
+        Ok(Threshold {
+            metric: metric.to_string(),
+            value,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct MetricStats {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    non_numeric: usize,
+    unmatched: usize,
+}
+
+impl MetricStats {
+    fn add(&mut self, value: &str) {
+        match value.parse::<f64>() {
+            Ok(v) => {
+                self.count += 1;
+                self.sum += v;
+                self.min = self.min.min(v);
+                self.max = self.max.max(v);
+            }
+            Err(_) => self.non_numeric += 1,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.count > 0 {
+            parts.push(format!(
+                "min {} / mean {} / max {} over {} tasks",
+                self.min,
+                self.sum / self.count as f64,
+                self.max,
+                self.count
+            ));
+        }
+        if self.non_numeric > 0 {
+            parts.push(format!("{} non-numeric", self.non_numeric));
+        }
+        if self.unmatched > 0 {
+            parts.push(format!("{} unmatched", self.unmatched));
+        }
+        parts.join(", ")
+    }
+}
+
+impl Default for MetricStats {
+    fn default() -> Self {
+        MetricStats {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            non_numeric: 0,
+            unmatched: 0,
+        }
+    }
+}
+
+impl Summary {
+    fn add(&mut self, result: &TaskResult) {
+        self.tasks += 1;
+        self.serial_time += result.duration;
+        if let Some(usage) = result.usage {
+            self.cpu_time += usage.cpu_time;
+            if let Some((read, written)) = usage.io {
+                self.io.0 += 1;
+                self.io.1 += read;
+                self.io.2 += written;
+            }
+        }
+        if let Some(hash) = result.stdout_hash {
+            self.hashes
+                .entry(result.name.clone())
+                .or_default()
+                .insert(hash);
+        }
+        if result.warmup {
+            self.warmups += 1;
+            return;
+        }
+        if let Some(latency) = result.spawn_latency {
+            self.spawn.0 += 1;
+            self.spawn.1 += latency;
+            self.spawn.2 += result.duration;
+        }
+
+        for (name, value) in &result.metrics {
+            self.metrics.entry(name.clone()).or_default().add(value);
+        }
+        for name in &result.unmatched_metrics {
+            self.metrics.entry(name.clone()).or_default().unmatched += 1;
+        }
+
+        // Tasks without the dimension are in none of the groups
+        let group = self.group_by.as_ref().and_then(|dim| {
+            result
+                .dims
+                .iter()
+                .find(|(d, _)| d == dim)
+                .map(|(_, value)| value)
+        });
+        if let Some(group) = group {
+            let metrics = self.grouped.entry(group.clone()).or_default();
+            for (name, value) in &result.metrics {
+                metrics.entry(name.clone()).or_default().add(value);
+            }
+            for name in &result.unmatched_metrics {
+                metrics.entry(name.clone()).or_default().unmatched += 1;
+            }
+        }
+
+        self.check_thresholds(result);
+    }
+
+    /// Records the violated thresholds of a task. A metric that was not
+    /// captured or is not a number violates every threshold on it, since
+    /// the gate cannot pass without a value.
+    fn check_thresholds(&mut self, result: &TaskResult) {
+        let bounds = self
+            .fail_under
+            .iter()
+            .map(|t| (t, "below"))
+            .chain(self.fail_over.iter().map(|t| (t, "above")));
+        for (threshold, side) in bounds {
+            let value = result
+                .metrics
+                .iter()
+                .find(|(name, _)| *name == threshold.metric)
+                .map(|(_, value)| value);
+            let violation = match value {
+                None if result.unmatched_metrics.contains(&threshold.metric) => {
+                    format!("did not capture '{}'", threshold.metric)
+                }
+                None => continue,
+                Some(value) => match value.parse::<f64>() {
+                    Ok(v) if side == "below" && v >= threshold.value => continue,
+                    Ok(v) if side == "above" && v <= threshold.value => continue,
+                    Ok(_) => format!(
+                        "'{}' is {}, {} the threshold {}",
+                        threshold.metric, value, side, threshold.value
+                    ),
+                    Err(_) => format!("'{}' is not a number: {:?}", threshold.metric, value),
+                },
+            };
+            self.violations
+                .push(format!("Task {:?}: {}", result.name, violation));
+        }
+    }
+
+    fn print(&self) {
+        if self.warmups > 0 {
+            infoln!(
+                "[PRUN] Left {} warmup tasks out of the statistics",
+                self.warmups
+            );
+        }
+        for (name, stats) in &self.metrics {
+            infoln!("[PRUN] Metric '{}': {}", name, stats.describe());
+        }
+        if let Some(dim) = &self.group_by {
+            for (value, metrics) in &self.grouped {
+                for (name, stats) in metrics {
+                    infoln!(
+                        "[PRUN] Metric '{}' for {}={}: {}",
+                        name,
+                        dim,
+                        value,
+                        stats.describe()
+                    );
+                }
+            }
+        }
+
+        if !self.hashes.is_empty() {
+            let differing = self
+                .hashes
+                .iter()
+                .filter(|(_, hashes)| hashes.len() > 1)
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            infoln!(
+                "[PRUN] Determinism: {} tasks deterministic, {} nondeterministic",
+                self.hashes.len() - differing.len(),
+                differing.len()
+            );
+            for name in differing {
+                infoln!(
+                    "[PRUN] Task {:?} wrote different stdout across repetitions",
+                    name
+                );
+            }
+        }
+
+        let (count, read, written) = self.io;
+        if read + written > 0 {
+            let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+            infoln!(
+                "[PRUN] Disk IO: read {:.1} MiB, wrote {:.1} MiB over {} tasks",
+                mib(read),
+                mib(written),
+                count
+            );
+        }
+
+        let (count, latency, duration) = self.spawn;
+        if count > 0 {
+            infoln!(
+                "[PRUN] Spawn overhead: mean {:?} until the first output, mean duration {:?}, over {} tasks",
+                latency / count as u32,
+                duration / count as u32,
+                count
+            );
+        }
+    }
+}
+
+/// Shows the board until the user closes the terminal UI, with the
+/// informational output hidden in the meantime.
+#[cfg(unix)]
+fn spawn_tui(
+    board: Arc<Board>,
+    finished: Arc<AtomicBool>,
+    color: bool,
+) -> Option<std::thread::JoinHandle<()>> {
+    match tui::Tui::open(color) {
+        Ok(tui) => {
+            INFO_HIDDEN.store(true, Ordering::Relaxed);
+            Some(std::thread::spawn(move || {
+                tui.run(&board, &finished);
+                INFO_HIDDEN.store(false, Ordering::Relaxed);
+            }))
+        }
+        Err(e) => {
+            eprintln!("[PRUN] Failed to open the terminal UI: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn spawn_tui(_: Arc<Board>, _: Arc<AtomicBool>, _: bool) -> Option<std::thread::JoinHandle<()>> {
+    eprintln!("[PRUN] Ignoring --tui, which is only supported on Unix");
+    None
+}
+
+/// Warns about more workers than cores, if the tasks turned out to be
+/// CPU-bound. IO-bound tasks can profit from oversubscription, so the
+/// worker count alone is no reason to warn. Instead the CPU time of all
+/// tasks is compared to the CPU time the cores could provide during the
+/// run: a saturated machine means the extra workers only added contention.
+fn check_oversubscription(summary: &Summary, workers: usize, elapsed: Duration) {
+    let cores = num_cpus::get();
+    if workers <= cores || elapsed.is_zero() {
+        return;
+    }
+
+    let load = summary.cpu_time.as_secs_f64() / (elapsed.as_secs_f64() * cores as f64);
+    if load >= 0.8 {
+        eprintln!(
+            "\n[PRUN] Warning: ran {} workers on {} cores, but the tasks were CPU-bound \
+             ({:.0}% core utilization). Consider --num-threads {} for better throughput \
+             (silence with --no-oversubscription-warning)",
+            workers,
+            cores,
+            load * 100.0,
+            cores
+        );
+    }
+}
+
+/// Prints the speedup over running the tasks one after another, and how
+/// close it comes to the ideal speedup of one per worker. The difference
+/// is mostly the idle tail of the run, while the last tasks complete.
+fn print_speedup(summary: &Summary, workers: usize, elapsed: Duration) {
+    if workers == 0 || elapsed.is_zero() || summary.serial_time.is_zero() {
+        return;
+    }
+    let speedup = summary.serial_time.as_secs_f64() / elapsed.as_secs_f64();
+    infoln!(
+        "[PRUN] Speedup: {:.2}x over serial execution ({:?} of tasks in {:?}), {:.1}% of the ideal {}x",
+        speedup,
+        summary.serial_time,
+        elapsed,
+        speedup / workers as f64 * 100.0,
+        workers
+    );
+}
+
+/// Prints the share of the run each worker spent running tasks. Workers
+/// far below the others were idle at the end of the run, waiting for the
+/// last long tasks on the other workers to complete.
+fn print_worker_report(busy: &[Duration], elapsed: Duration) {
+    for (i, busy) in busy.iter().enumerate() {
+        let utilization = if elapsed.is_zero() {
+            0.0
+        } else {
+            busy.as_secs_f64() / elapsed.as_secs_f64()
+        };
+        infoln!(
+            "[PRUN] Worker #{}: busy for {:.2}s of {:.2}s ({:.0}%)",
+            i,
+            busy.as_secs_f64(),
+            elapsed.as_secs_f64(),
+            utilization * 100.0
+        );
+    }
+}
+
+/// Resolves a program like the shell would: names containing a `/` are
+/// paths relative to `cwd`, everything else is looked up on `path`.
+fn which(program: &str, path: Option<&OsStr>, cwd: Option<&Path>) -> Option<PathBuf> {
+    fn is_executable(path: &Path) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            path.metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        }
+        #[cfg(not(unix))]
+        {
+            path.is_file()
+        }
+    }
+
+    if program.contains('/') {
+        let path = match cwd {
+            Some(cwd) => cwd.join(program),
+            None => PathBuf::from(program),
+        };
+        return is_executable(&path).then_some(path);
+    }
+
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Resolves the program of every task once, using the PATH the child
+/// would see, and replaces it with the path found. All tasks then run the
+/// same executable, even if PATH changes during the run. Programs that
+/// cannot be resolved are left as they are, so spawning them may still
+/// work (or fail with the usual error), and are returned with the number
+/// of tasks using them.
+fn resolve_programs(tasks: &mut VecDeque<Cmd>) -> BTreeMap<String, usize> {
+    let parent_path = std::env::var_os("PATH");
+    let mut missing = BTreeMap::new();
+    let mut resolved: HashMap<(String, Option<String>, Option<PathBuf>), Option<PathBuf>> =
+        HashMap::new();
+
+    for cmd in tasks.iter_mut().filter(|cmd| !cmd.barrier) {
+        // The program of a container is resolved inside of it, but the
+        // runtime has to exist on the PATH of prun
+        let (program, child_path) = match &mut cmd.container {
+            Some(container) => (&mut container.runtime, None),
+            None => {
+                let child_path = cmd
+                    .env
+                    .iter()
+                    .find(|(k, _)| k == "PATH")
+                    .map(|(_, v)| v.clone());
+                (&mut cmd.program, child_path)
+            }
+        };
+        let key = (program.clone(), child_path, cmd.cwd.clone());
+        let found = resolved
+            .entry(key)
+            .or_insert_with_key(|(program, path, cwd)| {
+                let path = path.as_deref().map(OsStr::new).or(parent_path.as_deref());
+                which(program, path, cwd.as_deref())
+            });
+        match found {
+            Some(path) => *program = path.to_string_lossy().to_string(),
+            None => *missing.entry(program.clone()).or_default() += 1,
+        }
+    }
+    missing
+}
+
+/// Fails with a list of all programs that could not be resolved, unless
+/// every program of the run can be found and executed.
+fn verify_commands(missing: &BTreeMap<String, usize>) -> Result<(), String> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let lines = missing
+        .iter()
+        .map(|(program, count)| {
+            format!(
+                "[PRUN] Command '{}' not found or not executable (used by {} tasks)",
+                program, count
+            )
+        })
+        .collect::<Vec<_>>();
+    Err(lines.join("\n"))
+}
+
+/// Runs the preflight check through the shell. The check is only expected
+/// to inspect the environment, so its stdout is discarded and only stderr
+/// is reported if it fails.
+///
+/// The check gets the same environment as the tasks, so `--clean-env`
+/// applies to it as well. `PRUN_CONFIG` is always set to the path of the
+/// config file, unless replaying a plan.
+fn run_preflight(cmd: &str, env: &EnvPolicy, config: Option<&Path>) -> Result<(), String> {
+    let mut command = Command::new("sh");
+    if env.clean {
+        command.env_clear();
+    }
+    command.envs(env.resolve(&HashMap::new(), false));
+    if let Some(config) = config {
+        command.env("PRUN_CONFIG", config);
+    }
+
+    let out = command
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("Failed to run preflight '{}': {}", cmd, e))?;
+
+    if !out.status.success() {
+        let mut message = format!("Preflight '{}' failed ({}), aborting", cmd, out.status);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        for line in stderr.lines() {
+            message.push_str(&format!("\n[PREFLIGHT] {}", line));
+        }
+        return Err(message);
+    }
+
+    Ok(())
+}
+
+/// The commit checked out in the working directory, recorded with the
+/// results so they can be traced back to the code that produced them.
+struct GitHead {
+    commit: String,
+    /// Whether tracked files differ from the commit.
+    dirty: bool,
+}
+
+impl GitHead {
+    /// Returns `None` outside of a git repository, or if git is missing.
+    fn current() -> Option<GitHead> {
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .ok()
+                .filter(|out| out.status.success())
+                .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        };
+
+        let commit = git(&["rev-parse", "HEAD"])?;
+        let dirty = git(&["status", "--porcelain", "--untracked-files=no"])?;
+        Some(GitHead {
+            commit,
+            dirty: !dirty.is_empty(),
+        })
+    }
+
+    fn to_json(&self) -> Json {
+        Json::obj()
+            .with("commit", self.commit.as_str())
+            .with("dirty", self.dirty)
+    }
+}
+
+/// Parses the command line, defaulting to the `run` subcommand if the
+/// first argument is none of the subcommands.
+fn parse_args() -> Cli {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let explicit = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        matches!(
+            arg,
+            "run" | "list" | "check" | "plan" | "help" | "-h" | "--help" | "-V" | "--version"
+        )
+    });
+    if !explicit {
+        args.insert(1.min(args.len()), "run".into());
+    }
+    Cli::from_iter(args)
+}
+
+/// Every subcommand exits with 1 if it fails, after printing why.
+fn main() {
+    let result = match parse_args() {
+        Cli::Run(opt) => run(opt),
+        Cli::List(selection) => list(selection),
+        Cli::Check(selection) => check(selection),
+        Cli::Plan(plan) => print_selected_plan(plan),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn list(mut selection: Selection) -> Result<(), String> {
+    let (tasks, _) = select_tasks(&mut selection)?;
+    for cmd in tasks.iter().filter(|cmd| !cmd.barrier) {
+        println!("{}", cmd.name);
+    }
+    Ok(())
+}
+
+fn check(mut selection: Selection) -> Result<(), String> {
+    let (mut tasks, _) = select_tasks(&mut selection)?;
+    verify_commands(&resolve_programs(&mut tasks))?;
+    let total = tasks.iter().filter(|cmd| !cmd.barrier).count();
+    println!("[PRUN] The config is valid, {} tasks", total);
+    Ok(())
+}
+
+fn print_selected_plan(mut plan: PlanOpt) -> Result<(), String> {
+    let (tasks, _) = select_tasks(&mut plan.selection)?;
+    if plan.explain {
+        for cmd in &tasks {
+            println!("{}: {}", cmd.name, cmd.explain());
+        }
+    } else if plan.summary {
+        print_plan_summary(&tasks);
+    } else {
+        print_plan(&tasks);
+    }
+    Ok(())
+}
+
+fn print_plan(tasks: &VecDeque<Cmd>) {
+    let plan = Json::obj().with("tasks", Json::Arr(tasks.iter().map(Cmd::to_json).collect()));
+    println!("{}", plan);
+}
+
+/// Loads the tasks from the config or the replayed plan and selects the
+/// ones to run. Returns them with the number of tasks skipped since their
+/// output is up to date.
+///
+/// Besides running the generators of the config, this has no side
+/// effects, so the read-only subcommands share it. Everything else that
+/// must only happen before a run, like the preflight, is left to `run`.
+fn select_tasks(opt: &mut Selection) -> Result<(VecDeque<Cmd>, usize), String> {
+    from_env_if_empty(&mut opt.filter, "PRUN_FILTER");
+    from_env_if_empty(&mut opt.only_tag, "PRUN_ONLY_TAG");
+
+    let config = match (&opt.replay, &opt.config) {
+        (Some(_), _) => None,
+        (None, Some(path)) => Some(load_config(path)?),
+        (None, None) => unreachable!("config is required without --replay"),
+    };
+
+    let env = opt.env_policy();
+
+    let mut tasks = if let Some(plan) = &opt.replay {
+        load_plan(plan)?
+    } else {
+        let config = config.unwrap();
+        let expanded = config
+            .tasks
+            .iter()
+            .filter(|(_, cmd)| {
+                opt.only_tag.is_empty() || cmd.tags.iter().any(|t| opt.only_tag.contains(t))
+            })
+            .map(|(name, cmd)| {
+                cmd.to_concreate_tasks(
+                    name,
+                    &env,
+                    &config.presets,
+                    &config.groups,
+                    opt.float_precision,
+                    &opt.container_runtime,
+                    opt.container.as_deref(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>();
+
+        let mut tasks = match expanded? {
+            tasks if opt.fair => interleave(tasks),
+            tasks => tasks.into_iter().flatten().collect::<VecDeque<_>>(),
+        };
+        // Before any filtering, so the index of a task stays the same
+        for (index, cmd) in tasks.iter_mut().filter(|cmd| !cmd.barrier).enumerate() {
+            cmd.substitute_index("${index}", index);
+            cmd.index = index;
+        }
+        tasks
+    };
+
+    if let Some(index) = opt.continue_from {
+        // Counted by ${index}, barriers are not counted and always kept
+        let total = tasks.iter().filter(|cmd| !cmd.barrier).count();
+        tasks.retain(|cmd| cmd.barrier || cmd.index >= index);
+        if index >= total {
+            eprintln!(
+                "[PRUN] Skipping all {} tasks, since --continue-from {} is past the end, nothing to run",
+                total, index
+            );
+        } else {
+            eprintln!(
+                "[PRUN] Skipping the first {} tasks, running tasks {} to {} of {}",
+                index,
+                index + 1,
+                total,
+                total
+            );
+        }
+    }
+
+    // Tasks of the config got the container when expanded, the ones of a
+    // replayed plan keep the environment they were planned with
+    if let (Some(image), Some(_)) = (&opt.container, &opt.replay) {
+        for cmd in tasks.iter_mut().filter(|cmd| !cmd.barrier) {
+            cmd.container.get_or_insert_with(|| Container {
+                runtime: opt.container_runtime.clone(),
+                image: image.clone(),
+            });
+        }
+    }
+
+    if !opt.filter.is_empty() {
+        tasks.retain(|cmd| opt.filter.iter().any(|f| cmd.name.contains(f.as_str())));
+    }
+
+    let mut up_to_date = 0;
+    if opt.incremental {
+        tasks.retain(|cmd| {
+            let run = !cmd.is_up_to_date();
+            up_to_date += usize::from(!run);
+            run
+        });
+    }
+
+    if opt.random_assignment {
+        let seed = opt.seed.unwrap_or_else(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            now.as_nanos() as u64 ^ u64::from(std::process::id())
+        });
+        eprintln!("[PRUN] Shuffled the tasks with --seed {}", seed);
+        shuffle(&mut tasks, seed);
+    }
+
+    if let Some(max) = opt.max_name_length {
+        for cmd in tasks.iter_mut() {
+            cmd.name = shorten_name(&cmd.name, max);
+        }
+    }
+
+    Ok((tasks, up_to_date))
+}
+
+/// The `run` subcommand.
+fn run(mut opt: Opt) -> Result<(), String> {
+    let (mut tasks, up_to_date) = select_tasks(&mut opt.selection)?;
+
+    let mut resumed = 0;
+    if let (true, Some(path)) = (opt.resume, &opt.checkpoint) {
+        let done = match read_checkpoint(path) {
+            Ok(done) => done,
+            Err(e) => {
+                return Err(format!("Failed to read checkpoint '{:?}': {}", path, e));
+            }
+        };
+        tasks.retain(|cmd| {
+            let run = !done.contains(&cmd.name);
+            resumed += usize::from(!run);
+            run
+        });
+    }
+
+    let mut known = None;
+    if let Some(path) = &opt.only_new {
+        let previous = load_plan(path)?;
+        let previous = previous.iter().map(Cmd::identity).collect::<HashSet<_>>();
+        let len = tasks.len();
+        // Barriers are kept, since they have no identity of their own
+        tasks.retain(|cmd| cmd.barrier || !previous.contains(&cmd.identity()));
+        let new = tasks.iter().filter(|cmd| !cmd.barrier).count();
+        known = Some((path.clone(), new, len - tasks.len()));
+    }
+
+    if opt.print_plan_json {
+        eprintln!("[PRUN] --print-plan-json is deprecated, use `prun plan`");
+        print_plan(&tasks);
+        return Ok(());
+    }
+
+    if opt.explain {
+        eprintln!("[PRUN] --explain is deprecated, use `prun plan --explain`");
+        for cmd in &tasks {
+            println!("{}: {}", cmd.name, cmd.explain());
+        }
+        return Ok(());
+    }
+
+    if opt.plan_summary {
+        eprintln!("[PRUN] --plan-summary is deprecated, use `prun plan --summary`");
+        print_plan_summary(&tasks);
+        return Ok(());
+    }
+
+    // After the deprecated plan flags, which like `prun plan` only read
+    if let (Some(preflight), false) = (&opt.selection.preflight, opt.simulate) {
+        let selection = &opt.selection;
+        let config_path = selection
+            .replay
+            .is_none()
+            .then_some(())
+            .and(selection.config.as_deref());
+        run_preflight(preflight, &selection.env_policy(), config_path)?;
+    }
+
+    let missing = resolve_programs(&mut tasks);
+    if opt.verify_commands {
+        verify_commands(&missing)?;
+    }
+
+    if opt
+        .timeout
+        .into_iter()
+        .chain(opt.timeout_factor)
+        .any(|t| t <= 0.0)
+    {
+        return Err("[PRUN] --timeout and --timeout-factor must be positive".to_string());
+    }
+    if opt.verify_deterministic && opt.repeat < 2 {
+        return Err("[PRUN] --verify-deterministic needs --repeat 2 or more".to_string());
+    }
+    if let Some(dim) = &opt.group_by {
+        let dims = tasks
+            .iter()
+            .flat_map(|cmd| cmd.dims.iter().map(|(d, _)| d.as_str()))
+            .collect::<BTreeSet<_>>();
+        if !dims.contains(dim.as_str()) {
+            return Err(format!(
+                "[PRUN] --group-by: no task has a dimension '{}', the named dimensions are: {}",
+                dim,
+                dims.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+    let captured = tasks
+        .iter()
+        .flat_map(|cmd| cmd.capture.iter().map(|(name, _)| name.as_str()))
+        .collect::<BTreeSet<_>>();
+    if let Some(threshold) = opt
+        .fail_under
+        .iter()
+        .chain(&opt.fail_over)
+        .find(|t| !captured.contains(t.metric.as_str()))
+    {
+        return Err(format!(
+            "[PRUN] --fail-under/--fail-over: no task captures a metric '{}'",
+            threshold.metric
+        ));
+    }
+    if opt.repeat > 1 {
+        tasks = (0..opt.repeat)
+            .flat_map(|repeat| {
+                tasks.iter().map(move |cmd| Cmd {
+                    repeat: Some(repeat),
+                    ..cmd.clone()
+                })
+            })
+            .collect();
+    }
+
+    let autotuned = if opt.autotune || opt.autotune_only {
+        if opt.autotune_budget <= 0.0 {
+            return Err("[PRUN] --autotune-budget must be positive".to_string());
+        }
+        let budget = Duration::from_secs_f64(opt.autotune_budget);
+        infoln!(
+            "[PRUN] Autotuning on samples of the tasks for up to {:?}",
+            budget
+        );
+        let sample = tasks.iter().filter(|cmd| !cmd.barrier).collect::<Vec<_>>();
+        let levels = autotune::probe(&sample, budget);
+        infoln!("[PRUN] Workers  Tasks  Time          Tasks/s");
+        for level in &levels {
+            infoln!(
+                "[PRUN] {:>7}  {:>5}  {:<12}  {:>7.2}",
+                level.workers,
+                level.tasks,
+                format!("{:.2?}", level.elapsed),
+                level.throughput()
+            );
+        }
+        let best = autotune::recommend(&levels);
+        if let Some(best) = best {
+            infoln!("[PRUN] Recommended --num-threads {}", best);
+        }
+        if opt.autotune_only {
+            return Ok(());
+        }
+        best
+    } else {
+        None
+    };
+
+    let names = tasks
+        .iter()
+        .filter(|cmd| !cmd.barrier)
+        .map(|cmd| &cmd.task)
+        .collect::<BTreeSet<_>>();
+    // Every record is a single line of JSON, since JSON strings escape all
+    // line breaks. A reader can therefore parse each line once its `\n` has
+    // arrived, and a last line without one is an incomplete record.
+    let output = if opt.output_on_stdout {
+        if opt.format != Format::Json {
+            return Err(
+                "--output-on-stdout writes newline-delimited JSON and needs --format json"
+                    .to_string(),
+            );
+        }
+        INFO_ON_STDERR.store(true, Ordering::Relaxed);
+        match Output::stdout() {
+            Ok(f) => Output::File(f),
+            Err(e) => {
+                return Err(format!("Failed to write the output to stdout: {}", e));
+            }
+        }
+    } else if let Some(output) = opt.output.clone() {
+        match Output::open(&output) {
+            Ok(f) => Output::File(f),
+            Err(e) => {
+                return Err(format!("Failed to open output file '{:?}': {}", output, e));
+            }
+        }
+    } else if let Some(dir) = opt.output_dir.clone() {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            return Err(format!(
+                "Failed to create output directory '{:?}': {}",
+                dir, e
+            ));
+        }
+
+        if opt.split_output_by_task && opt.format == Format::RunJson {
+            return Err(
+                "--format run-json writes a single document, which cannot be split by task"
+                    .to_string(),
+            );
+        }
+
+        if opt.split_output_by_task {
+            Output::split(dir, names.iter().copied())?
+        } else {
+            let path = dir.join("output.txt");
+            match Output::open(&path) {
+                Ok(f) => Output::File(f),
+                Err(e) => {
+                    return Err(format!("Failed to open output file '{:?}': {}", path, e));
+                }
+            }
+        }
+    } else {
+        Output::None
+    };
+
+    let total = tasks.iter().filter(|cmd| !cmd.barrier).count();
+    let n = autotuned
+        .or(opt.num_threads)
+        .unwrap_or(num_cpus::get() / 2)
+        .min(total);
 
-fn main() {
-    let opt = Opt::from_args();
-    if !opt.config.exists() {
-        eprintln!("Could not find config file '{:?}'", opt.config);
-        return;
-    }
+    let db = if let Some(path) = &opt.db {
+        let run_id = format!("{}-{}", unix_now(), std::process::id());
+        match Db::open(path, run_id) {
+            Ok(db) => Some(db),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to open results database '{:?}': {}",
+                    path, e
+                ));
+            }
+        }
+    } else {
+        None
+    };
 
-    let mut file = match File::open(&opt.config) {
-        Ok(f) => BufReader::new(f),
+    let target = match opt.output_target.map(LogTarget::open).transpose() {
+        Ok(target) => target,
         Err(e) => {
-            eprintln!("Could not open config file '{:?}': {}", opt.config, e);
-            return;
+            return Err(format!("Failed to open the system log: {}", e));
         }
     };
 
-    let mut string = String::new();
-    if let Err(e) = file.read_to_string(&mut string) {
-        eprintln!("Failed to read config file '{:?}': {}", opt.config, e);
-        return;
-    }
+    let checkpoint = match &opt.checkpoint {
+        Some(path) => match open_checkpoint(path, opt.resume) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                return Err(format!("Failed to open checkpoint '{:?}': {}", path, e));
+            }
+        },
+        None => None,
+    };
+
+    let git = GitHead::current().map(Arc::new);
+    let document = (opt.format == Format::RunJson).then(|| {
+        let source = opt
+            .selection
+            .replay
+            .as_ref()
+            .or(opt.selection.config.as_ref());
+        let hash = source
+            .and_then(|path| std::fs::read(path).ok())
+            .map(|bytes| format!("{:016x}", fnv1a(&bytes)));
+        let mut meta = Json::obj()
+            .with("source", source.map(|p| p.to_string_lossy().to_string()))
+            .with("source_hash", hash)
+            .with("host", hostname())
+            .with("started", unix_now())
+            .with("workers", n)
+            .with("tasks", total);
+        if let Some(git) = &git {
+            meta = meta.with("git", git.to_json());
+        }
+        RunDocument {
+            meta,
+            plan: Json::Arr(tasks.iter().map(Cmd::to_json).collect()),
+            results: Vec::new(),
+        }
+    });
 
-    let tasks = match toml::from_str::<Tasks>(&string) {
-        Ok(tasks) => tasks,
+    let sink = match opt.result_sink.as_deref().map(ResultSink::open).transpose() {
+        Ok(sink) => sink,
         Err(e) => {
-            eprintln!("Failed to parse config file'{:?}': {}", opt.config, e);
-            return;
+            return Err(format!("Failed to start the result sink: {}", e));
         }
     };
 
-    let tasks = tasks
-        .into_iter()
-        .map(|(name, cmd)| {
-            cmd.to_concreate_tasks(&name).into_iter()
-            // .map(|cmd| (name.clone(), cmd))
+    if opt.tui && !(std::io::stdin().is_terminal() && std::io::stdout().is_terminal()) {
+        eprintln!("[PRUN] Ignoring --tui since the terminal is not interactive");
+        opt.tui = false;
+    }
+    let board = opt.tui.then(|| {
+        let names = tasks.iter().filter(|cmd| !cmd.barrier);
+        Arc::new(Board::new(names.map(|cmd| cmd.name.as_str())))
+    });
+
+    let sinks = Sinks {
+        output,
+        document,
+        db,
+        target,
+        checkpoint,
+        sink,
+        board: board.clone(),
+        textfile: opt
+            .prometheus_textfile
+            .clone()
+            .map(|path| Textfile::new(path, Instant::now())),
+    };
+
+    let results = Arc::new(ResultQueue::new(opt.queue_capacity, opt.queue_policy));
+    let writer = {
+        let results = results.clone();
+        let format = opt.format;
+        let unbuffered =
+            opt.unbuffered || opt.output_on_stdout || total <= opt.unbuffered_threshold;
+        let git = git.clone();
+        let summary = Summary {
+            group_by: opt.group_by.clone(),
+            fail_under: opt.fail_under.clone(),
+            fail_over: opt.fail_over.clone(),
+            ..Summary::default()
+        };
+        std::thread::spawn(move || {
+            write_results(&results, sinks, format, unbuffered, git.as_deref(), summary)
         })
-        .flatten()
-        .collect::<VecDeque<_>>();
+    };
 
-    let n = opt
-        .num_threads
-        .unwrap_or(num_cpus::get() / 2)
-        .min(tasks.len());
-
-    let output = if let Some(output) = opt.output.clone() {
-        let f = match OpenOptions::new()
-            .append(true)
-            .write(true)
-            .create(true)
-            .open(&output)
-        {
-            Ok(f) => f,
+    if opt.simulate {
+        infoln!("[PRUN] Simulating {} tasks", total);
+    } else {
+        infoln!("[PRUN] Running {} tasks on {} processes", total, n);
+    }
+
+    let done = Arc::new(AtomicUsize::new(0));
+    let skipped = Arc::new(AtomicUsize::new(0));
+    let force = opt.force;
+
+    let trace = match &opt.trace {
+        Some(path) => match Trace::create(path, n) {
+            Ok(trace) => Some(Arc::new(trace)),
             Err(e) => {
-                eprintln!("Failed to open output file '{:?}': {}", output, e);
-                return;
+                return Err(format!("Failed to create trace file '{:?}': {}", path, e));
             }
-        };
+        },
+        None => None,
+    };
 
-        Some(BufWriter::new(f))
-    } else {
-        None
+    let logs = match opt.log_dir.clone() {
+        Some(dir) => match LogDir::create(dir.clone(), opt.log_budget) {
+            Ok(logs) => Some(Arc::new(logs)),
+            Err(e) => {
+                return Err(format!("Failed to create log directory '{:?}': {}", dir, e));
+            }
+        },
+        None => None,
+    };
+
+    let min_free_memory = match opt.min_free_memory {
+        Some(_) if available_memory().is_none() => {
+            eprintln!("[PRUN] Ignoring --min-free-memory, since /proc/meminfo cannot be read");
+            None
+        }
+        min => min,
     };
 
-    let output = Arc::new(Mutex::new(output));
+    let repro_dir = opt.repro_dir.clone().map(Arc::new);
+    if let Some(dir) = &repro_dir {
+        if let Err(e) = std::fs::create_dir_all(dir.as_path()) {
+            return Err(format!(
+                "Failed to create repro directory '{:?}': {}",
+                dir, e
+            ));
+        }
+    }
 
-    println!("[PRUN] Running {} tasks on {} processes", tasks.len(), n);
+    let running = Arc::new(Running::default());
+    let stop_control = Arc::new(AtomicBool::new(false));
+    let control = opt.control_file.clone().map(|path| {
+        let running = running.clone();
+        let stop = stop_control.clone();
+        std::thread::spawn(move || watch_control_file(&path, &running, &stop))
+    });
+    let timeouts = Arc::new(Timeouts {
+        fixed: opt.timeout.map(Duration::from_secs_f64),
+        factor: opt.timeout_factor,
+        durations: Mutex::default(),
+    });
+    let watchdog = (opt.timeout.is_some() || opt.timeout_factor.is_some()).then(|| {
+        let running = running.clone();
+        let timeouts = timeouts.clone();
+        let stop = stop_control.clone();
+        std::thread::spawn(move || watch_timeouts(&timeouts, &running, &stop))
+    });
 
-    let total = tasks.len();
-    let done = Arc::new(AtomicUsize::new(0));
+    let finished = Arc::new(AtomicBool::new(false));
+    // The terminal UI takes over stdout, whatever the informational output
+    let tui_color = opt.color.enabled_on(std::io::stdout().is_terminal());
+    let tui = board
+        .clone()
+        .and_then(|board| spawn_tui(board, finished.clone(), tui_color));
 
+    let t_start = Instant::now();
     let mut handles = Vec::with_capacity(n);
-    let tasks = Arc::new(Mutex::new(tasks));
+    let tasks = if opt.simulate {
+        for cmd in tasks.into_iter().filter(|cmd| !cmd.barrier) {
+            results.send(TaskResult::simulated(cmd));
+        }
+        VecDeque::new()
+    } else {
+        tasks
+    };
+    let assignment = allocate_workers(&tasks, n);
+    if assignment.iter().any(Option::is_some) {
+        let mut shares: Vec<(&str, usize)> = Vec::new();
+        for group in &assignment {
+            let group = group.as_deref().unwrap_or("(ungrouped)");
+            match shares.iter_mut().find(|(g, _)| *g == group) {
+                Some((_, share)) => *share += 1,
+                None => shares.push((group, 1)),
+            }
+        }
+        let shares = shares
+            .iter()
+            .map(|(g, share)| format!("{}={}", g, share))
+            .collect::<Vec<_>>();
+        infoln!("[PRUN] Workers per group: {}", shares.join(", "));
+    }
+    let tasks = Arc::new(Scheduler::new(tasks, n));
+    let pull_batch = opt.pull_batch.max(1);
 
     let verbose = opt.verbose;
+    let quiet_success = opt.quiet_success;
     let stdout = opt.stdout;
+    let profile_spawn = opt.profile_spawn;
+    let discard_first = opt.discard_first_per_worker;
+    let verify_deterministic = opt.verify_deterministic;
+    let color = opt.color.enabled();
+    let failed = Arc::new(AtomicBool::new(false));
+
+    let mem_sample_interval = opt.mem_sample_interval.map(Duration::from_millis);
+    if mem_sample_interval.is_some() && cfg!(not(target_os = "linux")) {
+        eprintln!("[PRUN] Ignoring --mem-sample-interval, since /proc is only available on Linux");
+    }
 
-    if !verbose {
-        print!("Progress [0/{}]", total);
-        std::io::stdout().flush().unwrap();
+    let step = match opt.step {
+        Some(0) => {
+            eprintln!("[PRUN] Ignoring --step 0");
+            None
+        }
+        Some(_) if opt.simulate => {
+            eprintln!("[PRUN] Ignoring --step, since --simulate runs no tasks");
+            None
+        }
+        Some(_) if !std::io::stdin().is_terminal() => {
+            eprintln!("[PRUN] Ignoring --step since stdin is not interactive");
+            None
+        }
+        step => step,
+    };
+    let gate = step.map(|step| Arc::new(Gate::new(step)));
+
+    if !verbose && !quiet_success && !opt.simulate {
+        info!("Progress [0/{}]", total);
     }
 
     for i in 0..n {
         let tasks = tasks.clone();
-        let output = output.clone();
+        let results = results.clone();
         let done = done.clone();
+        let skipped = skipped.clone();
+        let gate = gate.clone();
+        let trace = trace.clone();
+        let logs = logs.clone();
+        let repro_dir = repro_dir.clone();
+        let running = running.clone();
+        let timeouts = timeouts.clone();
+        let failed = failed.clone();
+        let board = board.clone();
+        let group = assignment.get(i).cloned().flatten();
 
         let handle = std::thread::spawn(move || {
             if verbose {
-                println!("[Worker #{}] Initalized", i);
+                infoln!("[Worker #{}] Initalized", i);
             }
 
+            let mut busy = Duration::ZERO;
+            let mut pulled = VecDeque::new();
+            let mut warmed_up = false;
             loop {
-                let mut lock = tasks.lock().unwrap();
-                let task = lock.pop_front();
-                drop(lock);
+                if pulled.is_empty() {
+                    pulled.extend(tasks.next(group.as_deref(), pull_batch));
+                }
+
+                if let Some(task) = pulled.pop_front() {
+                    if let Some(gate) = &gate {
+                        gate.acquire();
+                    }
+                    let done_file = task.skip_if_exists.as_deref().filter(|_| !force);
+                    if let Some(path) = done_file.filter(|path| path.exists()) {
+                        if verbose {
+                            infoln!(
+                                "[Worker #{}] Skipping task {:?}, since {:?} exists",
+                                i,
+                                task.name,
+                                path
+                            );
+                        }
+                        skipped.fetch_add(1, Ordering::SeqCst);
+                        if let Some(board) = &board {
+                            board.skip(&task.name);
+                        }
+                        tasks.complete(&task.task, task.repeat, None);
+                        if !verbose && !quiet_success {
+                            print_progress(&done, total, failed.load(Ordering::SeqCst), color);
+                        }
+                        if let Some(gate) = &gate {
+                            gate.complete();
+                        }
+                        continue;
+                    }
 
-                if let Some(task) = task {
-                    let Cmd { mut command, name } = task;
+                    let mut commands = vec![task.command()];
+                    commands.extend(task.retry_with.iter().map(|args| task.command_with(args)));
+                    let top_level = task.task.clone();
+                    let repro = repro_dir.is_some().then(|| task.clone());
+                    let Cmd {
+                        program,
+                        name,
+                        task,
+                        mut args,
+                        dims,
+                        capture,
+                        retry_with,
+                        keep_output_if,
+                        capture_output,
+                        repeat,
+                        position,
+                        index,
+                        ..
+                    } = task;
                     if verbose {
-                        println!("[Worker #{}] Running task: {:?}", i, name);
+                        infoln!("[Worker #{}] Running task: {:?}", i, name);
+                    }
+
+                    // Failed attempts are retried with the next variant
+                    let mut variant = 0;
+                    let attempt = loop {
+                        let command = &mut commands[variant];
+                        if gate.is_some() || board.is_some() {
+                            // Keep stdin free for resuming the run, or the keys of the TUI
+                            command.stdin(Stdio::null());
+                        }
+                        let launch = min_free_memory.map(|min| wait_for_memory(min, &running));
+                        if let Some(trace) = &trace {
+                            trace.begin(i, &name);
+                        }
+                        let t_spawn = Instant::now();
+                        let started = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs_f64())
+                            .unwrap_or(0.0);
+                        let child = match command.spawn() {
+                            Ok(child) => child,
+                            Err(e) => {
+                                if let Some(trace) = &trace {
+                                    trace.end(i, &name);
+                                }
+                                break Err(("start", e));
+                            }
+                        };
+                        let t0 = Instant::now();
+                        #[cfg(target_os = "linux")]
+                        let sampler = mem_sample_interval
+                            .map(|interval| MemorySampler::start(child.id(), interval));
+                        let id = running.insert(&name, child.id());
+                        if let Some(board) = &board {
+                            board.start(&name);
+                        }
+                        drop(launch);
+                        let waited = wait_with_usage(child, || running.remove(id));
+                        let killed = running.take_killed(id);
+                        let t1 = Instant::now();
+                        busy += t1 - t0;
+                        #[cfg(target_os = "linux")]
+                        let peak_rss = sampler.and_then(MemorySampler::finish);
+                        #[cfg(not(target_os = "linux"))]
+                        let peak_rss = None;
+                        if let Some(trace) = &trace {
+                            trace.end(i, &name);
+                        }
+                        let (pout, usage) = match waited {
+                            Ok(waited) => waited,
+                            Err(e) => break Err(("wait for", e)),
+                        };
+
+                        if killed.is_none() {
+                            timeouts.record(t1 - t0);
+                        }
+
+                        if pout.status.success() || killed.is_some() || variant == retry_with.len()
+                        {
+                            break Ok((pout, usage, killed, peak_rss, t_spawn, started, t0, t1));
+                        }
+                        variant += 1;
+                        if verbose {
+                            infoln!(
+                                "[Worker #{}] Retrying task {:?} with variant {}: {:?}",
+                                i,
+                                name,
+                                variant,
+                                retry_with[variant - 1]
+                            );
+                        }
+                    };
+                    if variant > 0 {
+                        args = retry_with[variant - 1].clone();
                     }
-                    let child = command.spawn().unwrap();
-                    let t0 = Instant::now();
-                    let pout = child.wait_with_output().unwrap();
-                    let t1 = Instant::now();
+                    let (pout, usage, killed, peak_rss, t_spawn, started, t0, t1) = match attempt {
+                        Ok(attempt) => attempt,
+                        Err((action, e)) => {
+                            // Like a failed task, so that its dependents are not held back
+                            eprintln!("\n[PRUN] Failed to {} task {:?}: {}", action, name, e);
+                            results.send(TaskResult {
+                                variant,
+                                repeat,
+                                index,
+                                error: Some(e.to_string()),
+                                ..TaskResult::unstarted(name, task, args, dims, program)
+                            });
+                            tasks.complete(&top_level, repeat, None);
+                            failed.store(true, Ordering::SeqCst);
+                            if !verbose && !quiet_success {
+                                print_progress(&done, total, true, color);
+                            }
+                            if let Some(gate) = &gate {
+                                gate.complete();
+                            }
+                            continue;
+                        }
+                    };
 
                     if verbose {
-                        println!(
-                            "[Worker #{}] Completed task: {:?} in {:?}",
+                        let status = if pout.status.success() { GREEN } else { RED };
+                        infoln!(
+                            "[Worker #{}] {}: {:?} in {:?}",
                             i,
+                            paint("Completed task", status, color),
                             name,
                             t1 - t0
                         );
                     }
-                    let output = output.lock();
-                    if let Ok(mut output) = output {
-                        if let Some(output) = output.as_mut() {
-                            if stdout {
-                                let string = String::from_utf8_lossy(&pout.stdout);
-                                let split = string.lines().last().unwrap();
-                                writeln!(
-                                    output,
-                                    "{}, {}, {}",
-                                    name,
-                                    (t1 - t0).as_secs_f64(),
-                                    split
-                                )
-                                .unwrap();
-                            } else {
-                                writeln!(output, "{}, {}", name, (t1 - t0).as_secs_f64()).unwrap();
+                    if quiet_success && !pout.status.success() {
+                        let reason = match (pout.status.code(), exit_signal(&pout.status)) {
+                            (Some(code), _) => format!("exit code {}", code),
+                            (None, Some(signal)) => format!("killed by signal {}", signal),
+                            (None, None) => pout.status.to_string(),
+                        };
+                        infoln!(
+                            "[PRUN] {}: {:?} with {}",
+                            paint("Failed task", RED, color),
+                            name,
+                            reason
+                        );
+                    }
+
+                    if let (Some(dir), Some(repro)) = (&repro_dir, &repro) {
+                        if !pout.status.success() {
+                            let script = repro.repro_script(&args, &pout.status);
+                            if let Err(e) = write_repro_script(dir, &name, &script) {
+                                eprintln!(
+                                    "[PRUN] Failed to write the repro script of {:?}: {}",
+                                    name, e
+                                );
                             }
-                            output.flush().unwrap();
                         }
                     }
 
-                    if !verbose {
-                        let v = done.fetch_add(1, Ordering::SeqCst);
-                        print!("\rProgress [{}/{}]", v + 1, total);
-                        std::io::stdout().flush().unwrap();
+                    if let Some(logs) = &logs {
+                        if let Err(e) = logs.write(&name, &pout.stdout) {
+                            eprintln!("[PRUN] Failed to write the log of {:?}: {}", name, e);
+                        }
+                    }
+
+                    let string = String::from_utf8_lossy(&pout.stdout);
+                    let output = keep_output_if.and_then(|regex| {
+                        // stderr was only captured for the match, so pass it on
+                        let _ = std::io::stderr().write_all(&pout.stderr);
+                        let stderr = String::from_utf8_lossy(&pout.stderr);
+                        (regex.is_match(&string) || regex.is_match(&stderr))
+                            .then(|| string.to_string())
+                    });
+                    let metric = if stdout {
+                        Some(string.lines().last().unwrap_or_default().to_string())
+                    } else {
+                        None
+                    };
+
+                    let mut metrics = Vec::new();
+                    let mut unmatched_metrics = Vec::new();
+                    for (metric, regex) in capture.iter() {
+                        match regex.captures(&string).and_then(|c| c[1].clone()) {
+                            Some(value) => metrics.push((metric.clone(), value)),
+                            None => unmatched_metrics.push(metric.clone()),
+                        }
+                    }
+                    if !unmatched_metrics.is_empty() {
+                        eprintln!(
+                            "\n[Worker #{}] Task {:?}: metrics {:?} did not match",
+                            i, name, unmatched_metrics
+                        );
+                    }
+
+                    results.send(TaskResult {
+                        name,
+                        task,
+                        args,
+                        dims,
+                        duration: t1 - t0,
+                        exit_code: pout.status.code(),
+                        metric,
+                        usage,
+                        metrics,
+                        unmatched_metrics,
+                        peak_rss,
+                        timestamp: unix_now(),
+                        spawn_latency: usage
+                            .and_then(|u| u.first_output)
+                            .filter(|_| profile_spawn)
+                            .map(|t| t - t_spawn),
+                        cancelled: killed == Some(Kill::Cancelled),
+                        timed_out: killed.filter(|kill| *kill != Kill::Cancelled),
+                        program,
+                        started,
+                        signal: exit_signal(&pout.status),
+                        warmup: discard_first && !warmed_up,
+                        simulated: false,
+                        variant,
+                        stdout_hash: verify_deterministic.then(|| fnv1a(&pout.stdout)),
+                        output,
+                        repeat,
+                        index,
+                        error: None,
+                    });
+                    warmed_up = true;
+                    let captured = capture_output.then(|| {
+                        let stdout = String::from_utf8_lossy(&pout.stdout);
+                        (position, stdout.trim_end().to_string())
+                    });
+                    tasks.complete(&top_level, repeat, captured);
+
+                    if !pout.status.success() {
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                    if !verbose && !quiet_success {
+                        print_progress(&done, total, failed.load(Ordering::SeqCst), color);
+                    }
+
+                    if let Some(gate) = &gate {
+                        gate.complete();
                     }
                 } else {
                     break;
                 }
             }
+            busy
         });
 
         handles.push(handle);
     }
 
-    for handle in handles {
-        handle.join().unwrap()
+    if let (Some(gate), Some(step)) = (&gate, step) {
+        gate.pause_every(step, total, |completed| {
+            info!(
+                "\n[PRUN] Completed {}/{} tasks, press Enter to continue",
+                completed, total
+            );
+            let mut line = String::new();
+            matches!(std::io::stdin().read_line(&mut line), Ok(n) if n > 0)
+        });
+    }
+
+    let busy = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+    let elapsed = t_start.elapsed();
+    stop_control.store(true, Ordering::Relaxed);
+    if let Some(control) = control {
+        control.join().unwrap();
+    }
+    if let Some(watchdog) = watchdog {
+        watchdog.join().unwrap();
+    }
+
+    results.close();
+    let (summary, written) = writer.join().unwrap();
+    finished.store(true, Ordering::Relaxed);
+    if let Some(tui) = tui {
+        tui.join().unwrap();
+    }
+    if !verbose && !quiet_success {
+        infoln!();
+    }
+    summary.print();
+    print_speedup(&summary, n, elapsed);
+    if resumed > 0 {
+        infoln!(
+            "[PRUN] Skipped {} tasks completed according to the checkpoint",
+            resumed
+        );
+    }
+    if up_to_date > 0 {
+        infoln!(
+            "[PRUN] Skipped {} tasks whose output is up to date",
+            up_to_date
+        );
+    }
+    let skipped = skipped.load(Ordering::SeqCst);
+    if skipped > 0 {
+        infoln!(
+            "[PRUN] Skipped {} tasks as already done, since their skip_if_exists file exists (rerun with --force)",
+            skipped
+        );
+    }
+    if let Some((path, new, known)) = &known {
+        infoln!(
+            "[PRUN] Found {} new tasks, skipped {} tasks of the plan {:?}",
+            new,
+            known,
+            path
+        );
+    }
+    if let Some(git) = &git {
+        infoln!(
+            "[PRUN] Git commit {}{}",
+            git.commit,
+            if git.dirty { " (dirty)" } else { "" }
+        );
+    }
+    if opt.worker_report {
+        print_worker_report(&busy, elapsed);
+    }
+
+    if results.dropped() > 0 {
+        eprintln!(
+            "[PRUN] Warning: dropped {} results since the output could not keep up",
+            results.dropped()
+        );
+    }
+
+    if let Some(trace) = &trace {
+        if let Err(e) = trace.write() {
+            eprintln!("[PRUN] Failed to write trace: {}", e);
+        }
+    }
+
+    if !opt.no_oversubscription_warning {
+        check_oversubscription(&summary, n, elapsed);
+    }
+
+    if let Err(e) = written {
+        return Err(format!("[PRUN] Failed to write the records: {}", e));
+    }
+
+    if !summary.violations.is_empty() {
+        let mut message = format!(
+            "[PRUN] Failed the metric thresholds, {} violations:",
+            summary.violations.len()
+        );
+        for violation in &summary.violations {
+            message.push_str(&format!("\n[PRUN]   {}", violation));
+        }
+        return Err(message);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory unique to the test.
+    fn temp_path(extension: &str) -> PathBuf {
+        static PATHS: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "prun-test-{}-{}.{}",
+            process::id(),
+            PATHS.fetch_add(1, Ordering::Relaxed),
+            extension
+        ))
+    }
+
+    /// Runs a subcommand on a config with the given flags.
+    fn parse<T: StructOpt>(
+        config: &str,
+        flags: &[&str],
+        f: impl FnOnce(T) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let path = temp_path("toml");
+        std::fs::write(&path, config).unwrap();
+        let args = ["prun"].iter().copied().chain(flags.iter().copied());
+        let result = f(T::from_iter(args.chain([path.to_str().unwrap()])));
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    /// Selects the tasks of a config like the subcommands do, with the
+    /// given flags.
+    fn select(config: &str, flags: &[&str]) -> Result<VecDeque<Cmd>, String> {
+        let path = temp_path("toml");
+        std::fs::write(&path, config).unwrap();
+        let args = ["prun"].iter().copied().chain(flags.iter().copied());
+        let mut selection = Selection::from_iter(args.chain([path.to_str().unwrap()]));
+        let selected = select_tasks(&mut selection);
+        let _ = std::fs::remove_file(&path);
+        selected.map(|(tasks, _)| tasks)
+    }
+
+    #[test]
+    fn only_run_runs_the_preflight() {
+        let marker = temp_path("preflight");
+        let preflight = format!("touch {}", marker.display());
+        let flags = ["--preflight", preflight.as_str()];
+        let config = "[a]\ncommand = \"true\"\n";
+        parse(config, &flags, list).unwrap();
+        parse(config, &flags, check).unwrap();
+        parse(config, &flags, print_selected_plan).unwrap();
+        parse(
+            config,
+            &["--summary", flags[0], flags[1]],
+            print_selected_plan,
+        )
+        .unwrap();
+        let simulate = ["--simulate", "--quiet-success", flags[0], flags[1]];
+        parse(config, &simulate, run).unwrap();
+        assert!(!marker.exists());
+
+        parse(config, &["--quiet-success", flags[0], flags[1]], run).unwrap();
+        assert!(marker.exists());
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn write_errors_fail_the_run() {
+        let config =
+            "[a]\ncommand = \"true\"\nargs = [{ type = \"Choice\", content = [\"1\", \"2\"] }]\n";
+        let flags = [
+            "--quiet-success",
+            "-n",
+            "1",
+            "--queue-capacity",
+            "1",
+            "--output",
+            "/dev/full",
+        ];
+        assert_eq!(
+            parse(config, &flags, run),
+            Err(
+                "[PRUN] Failed to write the records: No space left on device (os error 28)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn verify_commands_fails_on_missing_programs() {
+        let mut tasks = select(
+            "[ok]\ncommand = \"sh\"\n\n[missing]\ncommand = \"prun-no-such-program\"\n",
+            &[],
+        )
+        .unwrap();
+        let missing = resolve_programs(&mut tasks);
+        assert_eq!(
+            verify_commands(&missing),
+            Err(
+                "[PRUN] Command 'prun-no-such-program' not found or not executable (used by 1 tasks)"
+                    .to_string()
+            )
+        );
+
+        let mut tasks = select("[ok]\ncommand = \"sh\"\n", &[]).unwrap();
+        assert_eq!(verify_commands(&resolve_programs(&mut tasks)), Ok(()));
+    }
+
+    /// Runs `total` tasks on `workers` threads that take a permit of the
+    /// gate for every task like the workers of `run`, pausing every `step`
+    /// tasks. Returns the pauses, or `None` if the run does not finish.
+    fn step_through(total: usize, step: usize, workers: usize) -> Option<Vec<usize>> {
+        let gate = Arc::new(Gate::new(step));
+        let queue = Arc::new(Mutex::new(total));
+        let (done, finished) = std::sync::mpsc::channel();
+        for _ in 0..workers {
+            let gate = gate.clone();
+            let queue = queue.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                loop {
+                    let mut queue = queue.lock().unwrap();
+                    if *queue == 0 {
+                        break;
+                    }
+                    *queue -= 1;
+                    drop(queue);
+                    gate.acquire();
+                    gate.complete();
+                }
+                let _ = done.send(());
+            });
+        }
+
+        let mut pauses = Vec::new();
+        gate.pause_every(step, total, |completed| {
+            pauses.push(completed);
+            true
+        });
+        for _ in 0..workers {
+            finished.recv_timeout(Duration::from_secs(5)).ok()?;
+        }
+        Some(pauses)
+    }
+
+    fn names(tasks: &[Cmd]) -> Vec<&str> {
+        tasks.iter().map(|cmd| cmd.name.as_str()).collect()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    const PHASES: &str = r#"
+        [a]
+        command = "sh"
+        args = [{ type = "Choice", content = ["1", "2"] }]
+
+        [b]
+        command = "sh"
+        depends_on = ["a"]
+
+        [phase]
+        barrier = true
+        depends_on = ["b"]
+
+        [c]
+        command = "sh"
+        depends_on = ["phase"]
+    "#;
+
+    #[test]
+    fn dependencies_hold_back_tasks() {
+        let scheduler = Scheduler::new(select(PHASES, &[]).unwrap(), 1);
+        let first = scheduler.next(None, 10);
+        assert_eq!(names(&first), ["a,1", "a,2"]);
+
+        scheduler.complete("a", None, None);
+        scheduler.complete("a", None, None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["b"]);
+
+        // The barrier completes by itself once b has completed
+        scheduler.complete("b", None, None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["c"]);
+        scheduler.complete("c", None, None);
+        assert!(scheduler.next(None, 10).is_empty());
+    }
+
+    #[test]
+    fn captured_outputs_are_joined_in_plan_order() {
+        let config = r#"
+            [a]
+            command = "sh"
+            capture_output = true
+            args = [{ type = "Choice", content = ["1", "2"] }]
+
+            [b]
+            command = "sh"
+            depends_on = ["a"]
+            args = [{ type = "Static", content = "${a.output}" }]
+        "#;
+        let scheduler = Scheduler::new(select(config, &[]).unwrap(), 1);
+        let first = scheduler.next(None, 10);
+        assert_eq!(names(&first), ["a,1", "a,2"]);
+
+        // a,2 completes first, but its output still comes second
+        scheduler.complete("a", None, Some((first[1].position, "two".to_string())));
+        scheduler.complete("a", None, Some((first[0].position, "one".to_string())));
+        assert_eq!(scheduler.next(None, 10)[0].args, ["one two"]);
+    }
+
+    #[test]
+    fn serial_tasks_run_one_at_a_time() {
+        let config = r#"
+            [s]
+            command = "sh"
+            serial = true
+            args = [{ type = "Choice", content = ["1", "2", "3"] }]
+
+            [p]
+            command = "sh"
+            args = [{ type = "Choice", content = ["1", "2"] }]
+        "#;
+        let scheduler = Scheduler::new(select(config, &[]).unwrap(), 1);
+        // The other tasks are handed out alongside, but not s,2
+        assert_eq!(names(&scheduler.next(None, 10)), ["p,1", "p,2", "s,1"]);
+        scheduler.complete("p", None, None);
+        scheduler.complete("s", None, None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["s,2"]);
+        scheduler.complete("s", None, None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["s,3"]);
+    }
+
+    /// The groups of the workers for the tasks of `config`, `-` for the
+    /// tasks without a group.
+    fn workers(config: &str, n: usize) -> Vec<String> {
+        allocate_workers(&select(config, &[]).unwrap(), n)
+            .into_iter()
+            .map(|group| group.unwrap_or_else(|| "-".to_string()))
+            .collect()
+    }
+
+    const GROUPS: &str = r#"
+        [groups]
+        a = { weight = 1 }
+        b = { weight = 2 }
+        c = { weight = 1 }
+        unused = { weight = 5 }
+
+        [x]
+        command = "sh"
+        group = "a"
+
+        [y]
+        command = "sh"
+        group = "b"
+    "#;
+
+    #[test]
+    fn workers_are_shared_in_proportion_to_the_weights() {
+        assert_eq!(workers(GROUPS, 3), ["a", "b", "b"]);
+        assert_eq!(workers(GROUPS, 6), ["a", "a", "b", "b", "b", "b"]);
+    }
+
+    #[test]
+    fn remaining_workers_go_to_the_largest_remainders() {
+        // Shares of 4/3 and 8/3
+        assert_eq!(workers(GROUPS, 4), ["a", "b", "b", "b"]);
+        // Shares of 6/4, 12/4 and 6/4, the tie goes to the first group
+        let ungrouped = format!("{}\n[z]\ncommand = \"sh\"\n", GROUPS);
+        assert_eq!(workers(&ungrouped, 6), ["a", "a", "b", "b", "b", "-"]);
+    }
+
+    #[test]
+    fn fewer_workers_than_groups_leave_groups_without_workers() {
+        let config = format!("{}\n[z]\ncommand = \"sh\"\ngroup = \"c\"\n", GROUPS);
+        assert_eq!(workers(&config, 1), ["b"]);
+        assert_eq!(workers(&config, 2), ["a", "b"]);
+    }
+
+    #[test]
+    fn groups_without_tasks_get_no_workers() {
+        // The weight of `unused` is left out, so its share goes to the others
+        assert!(workers(GROUPS, 9).iter().all(|group| group != "unused"));
+        assert_eq!(workers(GROUPS, 9).len(), 9);
+        assert_eq!(workers("[x]\ncommand = \"sh\"\n", 2), ["-", "-"]);
+    }
+
+    #[test]
+    fn repeats_run_in_rounds() {
+        let tasks = select(
+            "[a]\ncommand = \"sh\"\n[b]\ncommand = \"sh\"\ndepends_on = [\"a\"]\n",
+            &[],
+        )
+        .unwrap();
+        let rounds = (0..2)
+            .flat_map(|repeat| {
+                tasks.iter().map(move |cmd| Cmd {
+                    repeat: Some(repeat),
+                    ..cmd.clone()
+                })
+            })
+            .collect();
+        let scheduler = Scheduler::new(rounds, 1);
+        let next = scheduler.next(None, 10);
+        assert_eq!(names(&next), ["a"]);
+        assert_eq!(next[0].repeat, Some(0));
+
+        // b of the first round depends on a of the first round only
+        scheduler.complete("a", Some(0), None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["b"]);
+        scheduler.complete("b", Some(0), None);
+        let next = scheduler.next(None, 10);
+        assert_eq!(names(&next), ["a"]);
+        assert_eq!(next[0].repeat, Some(1));
+        scheduler.complete("a", Some(1), None);
+        assert_eq!(names(&scheduler.next(None, 10)), ["b"]);
+        scheduler.complete("b", Some(1), None);
+        assert!(scheduler.next(None, 10).is_empty());
+    }
+
+    #[test]
+    fn dependencies_outside_the_selection_count_as_completed() {
+        let scheduler = Scheduler::new(select(PHASES, &["--filter", "c"]).unwrap(), 1);
+        assert_eq!(names(&scheduler.next(None, 10)), ["c"]);
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_dependencies() {
+        let error = |config: &str| select(config, &[]).err().unwrap();
+        assert_eq!(
+            error("[a]\ncommand = \"sh\"\ndepends_on = [\"x\"]\n"),
+            "Task 'a' depends on unknown task 'x'"
+        );
+        assert_eq!(
+            error("[a]\ncommand = \"sh\"\ndepends_on = [\"b\"]\n[b]\ncommand = \"sh\"\ndepends_on = [\"a\"]\n"),
+            "Tasks depend on each other through 'a'"
+        );
+        assert_eq!(
+            error("[a]\nbarrier = true\ncommand = \"sh\"\n"),
+            "Barrier 'a' must not have a command, args or a generator"
+        );
+        assert_eq!(error("[a]\nargs = []\n"), "Task 'a' has no command");
+    }
+
+    #[test]
+    fn adaptive_timeout_follows_the_median() {
+        let timeouts = Timeouts {
+            fixed: Some(Duration::from_secs(60)),
+            factor: Some(2.0),
+            durations: Mutex::default(),
+        };
+        for secs in [5, 1, 4, 2] {
+            timeouts.record(Duration::from_secs(secs));
+            assert_eq!(
+                timeouts.limit(),
+                Some((Duration::from_secs(60), Kill::Timeout))
+            );
+        }
+        timeouts.record(Duration::from_secs(3));
+        assert_eq!(
+            timeouts.limit(),
+            Some((Duration::from_secs(6), Kill::AdaptiveTimeout))
+        );
+        timeouts.record(Duration::from_secs(10));
+        assert_eq!(
+            timeouts.limit(),
+            Some((Duration::from_secs(8), Kill::AdaptiveTimeout))
+        );
+    }
+
+    #[test]
+    fn continue_from_skips_in_the_order_of_expansion() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [{ type = "Choice", content = ["1", "2"] }]
+
+            [b]
+            command = "sh"
+            args = [{ type = "Choice", content = ["1", "2"] }]
+        "#;
+        let selected =
+            |flags: &[&str]| names(select(config, flags).unwrap().make_contiguous()).join(" ");
+        assert_eq!(selected(&["--continue-from", "1"]), "a,2 b,1 b,2");
+        assert_eq!(selected(&["--continue-from", "1", "--filter", "a"]), "a,2");
+        assert_eq!(selected(&["--continue-from", "2", "--fair"]), "a,2 b,2");
+        assert_eq!(selected(&["--continue-from", "9"]), "");
+    }
+
+    #[test]
+    fn float_ranges_include_the_end() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [{ type = "Range", content = { from = 0.0, to = 0.3, step = 0.1 } }]
+        "#;
+        let selected =
+            |flags: &[&str]| names(select(config, flags).unwrap().make_contiguous()).join(" ");
+        assert_eq!(selected(&[]), "a,0 a,0.1 a,0.2 a,0.3");
+        assert_eq!(
+            selected(&["--float-precision", "2"]),
+            "a,0.00 a,0.10 a,0.20 a,0.30"
+        );
+        assert_eq!(
+            select(&config.replace("step = 0.1", "step = 0.0"), &[]).err(),
+            Some("Failed to expand task 'a': float range needs a step > 0, got 0".to_string())
+        );
+    }
+
+    #[test]
+    fn containers_only_get_the_kept_variables_given() {
+        let config = "[a]\ncommand = \"sh\"\nenv = { FOO = \"1\" }\n";
+        let env = |flags: &[&str]| {
+            let tasks = select(config, flags).unwrap();
+            let keys = tasks[0].env.iter().map(|(key, _)| key.clone());
+            keys.collect::<Vec<_>>().join(",")
+        };
+        assert_eq!(env(&["--clean-env"]), "PATH,HOME,FOO");
+        assert_eq!(env(&["--clean-env", "--container", "img"]), "FOO");
+        assert_eq!(
+            env(&["--clean-env", "--container", "img", "--env-keep", "PATH"]),
+            "PATH,FOO"
+        );
+    }
+
+    #[test]
+    fn indices_follow_the_order_of_expansion() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [
+                { type = "Choice", content = ["1", "2"] },
+                { type = "Static", content = "${index}/${local_index}" },
+            ]
+
+            [b]
+            command = "sh"
+            args = [
+                { type = "Choice", content = ["1", "2"] },
+                { type = "Static", content = "${index}/${local_index}" },
+            ]
+        "#;
+        let indices = |flags: &[&str]| {
+            let tasks = select(config, flags).unwrap();
+            let indices = tasks.iter().map(|cmd| cmd.args[1].clone());
+            indices.collect::<Vec<_>>().join(" ")
+        };
+        assert_eq!(indices(&[]), "0/0 1/1 2/0 3/1");
+        assert_eq!(indices(&["--fair"]), "0/0 1/0 2/1 3/1");
+        assert_eq!(indices(&["--filter", "b"]), "2/0 3/1");
+        assert_eq!(indices(&["--fair", "--filter", "b"]), "1/0 3/1");
+    }
+
+    #[test]
+    fn path_templates_use_the_placeholders_of_the_arguments() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [{ type = "Choice", content = ["1", "2"] }]
+            skip_if_exists = "out/${arg0}-${index}-${local_index}.txt"
+            output = "out/${arg0}.txt"
+        "#;
+        let tasks = select(config, &[]).unwrap();
+        let path = |cmd: &Cmd| cmd.skip_if_exists.clone().unwrap();
+        assert_eq!(path(&tasks[0]), PathBuf::from("out/1-0-0.txt"));
+        assert_eq!(path(&tasks[1]), PathBuf::from("out/2-1-1.txt"));
+        assert_eq!(tasks[1].output, Some(PathBuf::from("out/2.txt")));
+
+        assert_eq!(
+            render_template("out/${size}.txt", &[]),
+            Err("unknown dimension 'size' in template 'out/${size}.txt'".to_string())
+        );
+        assert_eq!(
+            render_template("out/{arg0}.txt", &[("arg0".to_string(), "1".to_string())]),
+            Ok("out/{arg0}.txt".to_string())
+        );
+    }
+
+    /// The combinations of a matrix, each as `axis=value` pairs.
+    fn matrix(toml: &str) -> Vec<String> {
+        let matrix: Matrix = toml::from_str(toml).unwrap();
+        let combinations = matrix.combinations(None).unwrap();
+        combinations
+            .iter()
+            .map(|c| {
+                let values = c.iter().map(|(axis, value)| format!("{}={}", axis, value));
+                values.collect::<Vec<_>>().join(",")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matrix_exclude_removes_matching_combinations() {
+        let combinations = matrix(
+            r#"
+            model = ["resnet", "vgg"]
+            lr = [1, 2]
+            exclude = [{ model = "vgg", lr = 1 }, { model = "resnet" }]
+        "#,
+        );
+        assert_eq!(combinations, ["lr=2,model=vgg"]);
+    }
+
+    #[test]
+    fn matrix_include_extends_matching_combinations() {
+        let combinations = matrix(
+            r#"
+            model = ["resnet", "vgg"]
+            lr = [1, 2]
+            include = [{ model = "vgg", batch = 32 }, { lr = 2, batch = 64 }]
+        "#,
+        );
+        assert_eq!(
+            combinations,
+            [
+                "lr=1,model=resnet",
+                "batch=32,lr=1,model=vgg",
+                "batch=64,lr=2,model=resnet",
+                // Added by both entries, the later one wins
+                "batch=64,lr=2,model=vgg",
+            ]
+        );
+    }
+
+    #[test]
+    fn matrix_include_appends_unmatched_entries() {
+        let combinations = matrix(
+            r#"
+            model = ["resnet", "vgg"]
+            include = [{ model = "vit", batch = 8 }, { model = "vgg" }]
+        "#,
+        );
+        assert_eq!(
+            combinations,
+            ["model=resnet", "model=vgg", "batch=8,model=vit"]
+        );
+    }
+
+    #[test]
+    fn float_precision_applies_to_every_float_source() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [
+                { type = "NumList", content = { values = [1.0, 2.5] } },
+                { type = "Range", content = { from = 1.0, to = 1.0, step = 1.0 } },
+                { type = "Range", content = { from = 1.0, ratio = 2.0, count = 1 } },
+            ]
+            [a.matrix]
+            x = [1.0]
+        "#;
+        let args = |flags: &[&str]| {
+            let tasks = select(config, flags).unwrap();
+            let args = tasks.iter().map(|cmd| cmd.args.join(" "));
+            args.collect::<Vec<_>>()
+        };
+        assert_eq!(args(&[]), ["1 1 1 --x=1", "2.5 1 1 --x=1"]);
+        assert_eq!(
+            args(&["--float-precision", "1"]),
+            ["1.0 1.0 1.0 --x=1.0", "2.5 1.0 1.0 --x=1.0"]
+        );
+    }
+
+    /// The records of a run with `--format json`.
+    fn run_records(config: &str, flags: &[&str]) -> Vec<Json> {
+        let output = temp_path("json");
+        let mut args = vec!["--quiet-success", "-n", "1", "--format", "json"];
+        args.extend(["--output", output.to_str().unwrap()]);
+        args.extend(flags);
+        let _ = parse(config, &args, run);
+        let records = std::fs::read_to_string(&output).unwrap_or_default();
+        let _ = std::fs::remove_file(&output);
+        records
+            .lines()
+            .map(|line| Json::parse(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn retries_apply_the_variants_and_record_the_winning_one() {
+        assert_eq!(
+            with_override(
+                &strings(&["-c", "--mode=bad", "-v"]),
+                &strings(&["--mode=ok", "-v", "-q"])
+            ),
+            ["-c", "--mode=ok", "-v", "-q"]
+        );
+
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [
+                { type = "Static", content = "-c" },
+                { type = "Static", content = "test \"$0\" = --mode=ok" },
+                { type = "Static", content = "--mode=bad" },
+            ]
+            retry_with = [["--mode=worse"], ["--mode=ok"], ["--mode=never"]]
+        "#;
+        let records = run_records(config, &[]);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(
+            record.get("variant").map(Json::to_string),
+            Some("2".to_string())
+        );
+        assert_eq!(
+            record.get("args").and_then(Json::as_array).unwrap()[2].as_str(),
+            Some("--mode=ok")
+        );
+        assert_eq!(
+            record.get("exit_code").map(Json::to_string),
+            Some("0".to_string())
+        );
+    }
+
+    /// The arguments of the tasks of a config, space-separated per task.
+    fn expanded(config: &str, flags: &[&str]) -> Result<Vec<String>, String> {
+        let tasks = select(config, flags)?;
+        Ok(tasks.iter().map(|cmd| cmd.args.join(" ")).collect())
+    }
+
+    #[test]
+    fn geometric_ranges_multiply_by_the_ratio() {
+        let config = |content: &str| {
+            format!(
+                "[a]\ncommand = \"sh\"\nargs = [{{ type = \"Range\", content = {} }}]\n",
+                content
+            )
+        };
+        assert_eq!(
+            expanded(
+                &config("{ from = 32.0, ratio = 2.0, count = 4, prefix = \"--batch=\" }"),
+                &[]
+            ),
+            Ok(strings(&[
+                "--batch=32",
+                "--batch=64",
+                "--batch=128",
+                "--batch=256"
+            ]))
+        );
+        // Rounded, unless one of the powers is not whole
+        assert_eq!(
+            expanded(&config("{ from = 1000.0, ratio = 1.1, count = 4 }"), &[]),
+            Ok(strings(&["1000", "1100", "1210", "1331"]))
+        );
+        assert_eq!(
+            expanded(&config("{ from = 1.0, ratio = 0.5, count = 3 }"), &[]),
+            Ok(strings(&["1", "0.5", "0.25"]))
+        );
+        assert_eq!(
+            expanded(&config("{ from = 1.0, ratio = 0.0, count = 3 }"), &[]),
+            Err("Failed to expand task 'a': geometric range needs a ratio > 0, got 0".to_string())
+        );
+        assert_eq!(
+            expanded(&config("{ from = 1.0, ratio = 2.0, count = 0 }"), &[]),
+            Err(
+                "Failed to expand task 'a': geometric range needs a count of at least 1"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn num_lists_keep_their_order_and_prefix() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [{ type = "NumList", content = { values = [0.3, 1, 0.05], prefix = "--lr=" } }]
+        "#;
+        assert_eq!(
+            expanded(config, &[]),
+            Ok(strings(&["--lr=0.3", "--lr=1", "--lr=0.05"]))
+        );
+        let tasks = select(config, &[]).unwrap();
+        assert_eq!(names(tasks.as_slices().0), ["a,0.3", "a,1", "a,0.05"]);
+        assert_eq!(tasks[2].dims, [("lr".to_string(), "0.05".to_string())]);
+    }
+
+    #[test]
+    fn presets_expand_inline_and_sweep() {
+        let config = r#"
+            [presets]
+            small = ["--size=1", "--batch=8"]
+            large = ["--size=9"]
+
+            [a]
+            command = "sh"
+            args = [
+                { type = "Preset", content = { name = "small" } },
+                { type = "Preset", content = { name = ["small", "large"] } },
+            ]
+        "#;
+        assert_eq!(
+            expanded(config, &[]),
+            Ok(strings(&[
+                "--size=1 --batch=8 --size=1 --batch=8",
+                "--size=1 --batch=8 --size=9",
+            ]))
+        );
+        let tasks = select(config, &[]).unwrap();
+        assert_eq!(
+            names(tasks.as_slices().0),
+            ["a,small,small", "a,small,large"]
+        );
+        assert_eq!(tasks[1].dims, [("arg1".to_string(), "large".to_string())]);
+        assert_eq!(
+            expanded(&config.replace("\"large\"]", "\"huge\"]"), &[]),
+            Err("Failed to expand task 'a': unknown preset 'huge'".to_string())
+        );
+    }
+
+    #[test]
+    fn generators_replace_the_args() {
+        let config =
+            |generator: &str| format!("[a]\ncommand = \"sh\"\ngenerator = {:?}\n", generator);
+        assert_eq!(
+            expanded(&config(r#"echo '[["x", 1], ["y z", true]]'"#), &[]),
+            Ok(strings(&["x 1", "y z true"]))
+        );
+        assert_eq!(
+            expanded(&config("echo '{}'"), &[]),
+            Err(
+                "Failed to expand task 'a': generator 'echo '{}'' must print a JSON array"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            expanded(&config("exit 3"), &[]),
+            Err(
+                "Failed to expand task 'a': generator 'exit 3' failed (exit status: 3)".to_string()
+            )
+        );
+        let both = config("echo '[]'") + "args = [{ type = \"Static\", content = \"x\" }]\n";
+        assert_eq!(
+            expanded(&both, &[]),
+            Err(
+                "Failed to expand task 'a': a generator replaces args, so both cannot be given"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn joblogs_follow_gnu_parallel() {
+        let config = r#"
+            [a]
+            command = "sh"
+            args = [
+                { type = "Static", content = "-c" },
+                { type = "Choice", content = ["exit 0", "kill $$"] },
+            ]
+        "#;
+        let output = temp_path("joblog");
+        let path = output.to_str().unwrap();
+        let flags = [
+            "--quiet-success",
+            "-n",
+            "1",
+            "--format",
+            "joblog",
+            "--output",
+            path,
+        ];
+        let _ = parse(config, &flags, run);
+        // Appended to without another header, numbered by the plan
+        let resumed = [&flags[..], &["--continue-from", "1"]].concat();
+        let _ = parse(config, &resumed, run);
+        let joblog = std::fs::read_to_string(&output).unwrap();
+        let _ = std::fs::remove_file(&output);
+
+        let lines = joblog.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], JOBLOG_HEADER);
+        let columns = |line: &str| line.split('\t').map(str::to_string).collect::<Vec<_>>();
+        let (ok, killed) = (columns(lines[1]), columns(lines[2]));
+        assert_eq!(ok.len(), 9);
+        assert_eq!((ok[0].as_str(), ok[1].as_str()), ("1", ":"));
+        assert_eq!(ok[6..8], strings(&["0", "0"]));
+        assert!(ok[8].ends_with("sh -c 'exit 0'"), "{}", ok[8]);
+        assert_eq!(killed[0], "2");
+        assert_eq!(killed[6..8], strings(&["-1", "15"]));
+        assert!(killed[8].ends_with("sh -c 'kill $$'"), "{}", killed[8]);
+        assert_eq!(columns(lines[3])[0], "2");
+        assert_eq!(columns(lines[3])[8], killed[8]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn repro_scripts_quote_what_they_run() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let config = r#"
+            [a]
+            command = "sh"
+            env = { GREETING = "hi there" }
+            args = [
+                { type = "Static", content = "-c" },
+                { type = "Static", content = "printf '%s|' \"$0\" \"$GREETING\" \"$1\" \"$PWD\"" },
+                { type = "Static", content = "it's" },
+                { type = "Static", content = "a $b `c`" },
+            ]
+        "#;
+        let dir = temp_path("it's dir");
+        std::fs::create_dir(&dir).unwrap();
+        let mut tasks = select(config, &[]).unwrap();
+        tasks[0].cwd = Some(dir.clone());
+        let script = tasks[0].repro_script(&tasks[0].args, &ExitStatus::from_raw(256));
+        let out = Command::new("sh").arg("-c").arg(&script).output().unwrap();
+        let _ = std::fs::remove_dir(&dir);
+        // The name is given in a comment, whatever it contains
+        assert_eq!(
+            script.lines().filter(|line| !line.starts_with('#')).count(),
+            2
+        );
+        assert!(script.contains(", which failed with exit status: 1\n"));
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            format!("it's|hi there|a $b `c`|{}|", dir.display())
+        );
+    }
+
+    #[test]
+    fn shortened_names_fit_and_stay_distinct() {
+        let names = ["a-long-task-name-1", "a-long-task-name-2"];
+        for max in NAME_HASH_LEN..=names[0].len() {
+            let short = names.map(|name| shorten_name(name, max));
+            assert!(
+                short.iter().all(|s| s.chars().count() <= max),
+                "{:?}",
+                short
+            );
+            assert_ne!(short[0], short[1]);
+        }
+        assert_eq!(shorten_name("short", 8), "short");
+        assert!(parse_max_name_length("7").is_err());
+        assert_eq!(parse_max_name_length("8"), Ok(8));
+    }
+
+    #[test]
+    fn step_finishes_when_total_is_a_multiple_of_step() {
+        assert_eq!(step_through(4, 2, 2), Some(vec![2]));
+        assert_eq!(step_through(6, 2, 3), Some(vec![2, 4]));
+        assert_eq!(step_through(2, 2, 4), Some(vec![]));
+    }
+
+    #[test]
+    fn step_finishes_when_total_is_not_a_multiple_of_step() {
+        assert_eq!(step_through(5, 2, 2), Some(vec![2, 4]));
+        assert_eq!(step_through(7, 3, 4), Some(vec![3, 6]));
+        assert_eq!(step_through(1, 3, 2), Some(vec![]));
     }
 }
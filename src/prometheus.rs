@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::File,
+    io::Write,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::TaskResult;
+
+/// A textfile for the textfile collector of the Prometheus node exporter,
+/// see `--prometheus-textfile`. It is written once after the run, to a
+/// temporary file next to it which is then renamed, so the collector
+/// never reads a partial file.
+///
+/// Every task is one sample of each task metric, labeled with its name
+/// and top-level task. Since samples must be unique, the last of several
+/// tasks of the same name (e.g. with `--repeat`) wins.
+pub struct Textfile {
+    path: PathBuf,
+    started: Instant,
+    tasks: BTreeMap<String, Sample>,
+    failed: usize,
+    count: usize,
+}
+
+struct Sample {
+    task: String,
+    duration: f64,
+    exit_code: i32,
+}
+
+impl Textfile {
+    pub fn new(path: PathBuf, started: Instant) -> Textfile {
+        Textfile {
+            path,
+            started,
+            tasks: BTreeMap::new(),
+            failed: 0,
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, result: &TaskResult) {
+        self.count += 1;
+        if result.exit_code != Some(0) {
+            self.failed += 1;
+        }
+        self.tasks.insert(
+            result.name.clone(),
+            Sample {
+                task: result.task.clone(),
+                duration: result.duration.as_secs_f64(),
+                // Like the joblog, tasks killed by a signal exit with -1
+                exit_code: result.exit_code.unwrap_or(-1),
+            },
+        );
+    }
+
+    pub fn write(&self) -> std::io::Result<()> {
+        let mut text = String::new();
+        let mut metric = |name: &str, help: &str, samples: &mut dyn Iterator<Item = String>| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} gauge", name);
+            for sample in samples {
+                let _ = writeln!(text, "{}{}", name, sample);
+            }
+        };
+        let labels = |name: &str, sample: &Sample| {
+            format!(
+                "{{task=\"{}\",top_level=\"{}\"}}",
+                escape(name),
+                escape(&sample.task)
+            )
+        };
+
+        metric(
+            "prun_task_duration_seconds",
+            "The duration of the task in the last run.",
+            &mut self
+                .tasks
+                .iter()
+                .map(|(name, s)| format!("{} {}", labels(name, s), s.duration)),
+        );
+        metric(
+            "prun_task_exit_code",
+            "The exit code of the task in the last run, -1 if killed by a signal.",
+            &mut self
+                .tasks
+                .iter()
+                .map(|(name, s)| format!("{} {}", labels(name, s), s.exit_code)),
+        );
+        metric(
+            "prun_run_tasks",
+            "The number of tasks that completed in the last run.",
+            &mut std::iter::once(format!(" {}", self.count)),
+        );
+        metric(
+            "prun_run_failed_tasks",
+            "The number of tasks that failed in the last run.",
+            &mut std::iter::once(format!(" {}", self.failed)),
+        );
+        metric(
+            "prun_run_duration_seconds",
+            "The duration of the last run.",
+            &mut std::iter::once(format!(" {}", self.started.elapsed().as_secs_f64())),
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        metric(
+            "prun_run_completion_timestamp_seconds",
+            "The time the last run completed, as a unix timestamp.",
+            &mut std::iter::once(format!(" {}", now)),
+        );
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Escapes a label value of the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,840 @@
+//! A small backtracking regex engine, covering the syntax commonly used to
+//! pick values out of program output: literals, `.`, classes like `[a-z]`,
+//! `\d` `\w` `\s` (and their negations), groups `(..)` and `(?:..)`,
+//! alternation, the quantifiers `*` `+` `?` `{n}` `{n,}` `{n,m}` (greedy or
+//! lazy with a trailing `?`) and the anchors `^` `$` `\b`.
+//!
+//! `^` and `$` match at line boundaries, since the inputs are usually the
+//! multi-line outputs of tasks.
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit(neg) => c.is_ascii_digit() != neg,
+            ClassItem::Word(neg) => is_word(c) != neg,
+            ClassItem::Space(neg) => c.is_whitespace() != neg,
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// A compiled regular expression.
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    prog: Vec<Inst>,
+    groups: usize,
+    line_anchored: bool,
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Regex({:?})", self.source)
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The most instructions a regex compiles to, which bounded repetitions
+/// like `(..){1000}` multiply.
+const MAX_PROG: usize = 100_000;
+
+/// The size of the set of visited states up to which searches are linear
+/// in the length of the text, in bits (8 MiB).
+const MAX_VISITED: usize = 1 << 26;
+
+impl Regex {
+    pub fn new(source: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let root = parser.alt()?;
+        if parser.pos != parser.chars.len() {
+            return Err(format!("unmatched ')' in regex '{}'", source));
+        }
+        let mut compiler = Compiler {
+            prog: Vec::new(),
+            marks: 0,
+        };
+        compiler.node(&root);
+        compiler.prog.push(Inst::Match);
+        if compiler.prog.len() > MAX_PROG {
+            return Err(format!("regex '{}' is too large", source));
+        }
+        Ok(Regex {
+            source: source.to_string(),
+            prog: compiler.prog,
+            groups: parser.groups,
+            line_anchored: line_anchored(&root),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The number of capture groups, not counting the whole match.
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    /// Whether the regex matches anywhere in the text.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.captures(text).is_some()
+    }
+
+    /// Finds the leftmost match and returns all capture groups, with
+    /// the whole match at index 0.
+    ///
+    /// The search backtracks with an explicit stack, so neither long
+    /// inputs nor long repetitions can overflow the stack of the thread.
+    /// Every state (instruction and position) is tried at most once, which
+    /// makes the search linear in the length of the text. For texts too
+    /// long to track the states of, the search instead gives up after as
+    /// many steps as tracking would allow, and reports no match.
+    pub fn captures(&self, text: &str) -> Option<Vec<Option<String>>> {
+        let chars = text.chars().collect::<Vec<_>>();
+        let states = self.prog.len().saturating_mul(chars.len() + 1);
+        let mut search = Search {
+            prog: &self.prog,
+            text: &chars,
+            visited: (states <= MAX_VISITED).then(|| vec![0u64; states.div_ceil(64)]),
+            budget: states,
+            slots: Vec::new(),
+            marks: vec![0; self.marks()],
+            stack: Vec::new(),
+        };
+        for start in 0..=chars.len() {
+            if self.line_anchored && start > 0 && chars[start - 1] != '\n' {
+                continue;
+            }
+
+            search.slots = vec![None; 2 * (self.groups + 1)];
+            match search.run(start) {
+                Outcome::Match => {
+                    let slots = std::mem::take(&mut search.slots);
+                    return Some(
+                        slots
+                            .chunks(2)
+                            .map(|slot| match *slot {
+                                [Some(a), Some(b)] => Some(chars[a..b].iter().collect()),
+                                _ => None,
+                            })
+                            .collect(),
+                    );
+                }
+                Outcome::NoMatch => {}
+                Outcome::GaveUp => return None,
+            }
+        }
+        None
+    }
+
+    fn marks(&self) -> usize {
+        self.prog
+            .iter()
+            .map(|inst| match inst {
+                Inst::Mark(k) => k + 1,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Patterns starting with `.*` can only match at line starts, since any
+/// match starting within a line also starts at the beginning of it.
+/// Skipping the other starts keeps searches like `.*x` from being
+/// quadratic in the line length.
+fn line_anchored(root: &Node) -> bool {
+    let first = match root {
+        Node::Concat(nodes) => nodes.first(),
+        _ => None,
+    };
+    matches!(
+        first,
+        Some(Node::Repeat { node, min: 0, max: None, greedy: true }) if matches!(**node, Node::Any)
+    )
+}
+
+/// An instruction of the program a regex compiles to. Threads run through
+/// the program, consuming a character with each of the first three.
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    /// Continues at the first target, backtracking to the second.
+    Split(usize, usize),
+    Jmp(usize),
+    /// Records the position in a capture slot, the start of group `n`
+    /// being slot `2n` and its end `2n + 1`.
+    Save(usize),
+    /// Records the position at the start of an iteration of a repetition
+    /// that can match the empty string.
+    Mark(usize),
+    /// Fails an iteration that did not advance from its `Mark`, since an
+    /// empty iteration can not make progress.
+    Progress(usize),
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    marks: usize,
+}
+
+impl Compiler {
+    fn node(&mut self, node: &Node) {
+        // Stop early, the regex is rejected anyway
+        if self.prog.len() > MAX_PROG {
+            return;
+        }
+        match node {
+            Node::Char(c) => self.prog.push(Inst::Char(*c)),
+            Node::Any => self.prog.push(Inst::Any),
+            Node::Class(items, negated) => self.prog.push(Inst::Class(items.clone(), *negated)),
+            Node::LineStart => self.prog.push(Inst::LineStart),
+            Node::LineEnd => self.prog.push(Inst::LineEnd),
+            Node::WordBoundary => self.prog.push(Inst::WordBoundary),
+            Node::Group(inner, None) => self.node(inner),
+            Node::Group(inner, Some(idx)) => {
+                self.prog.push(Inst::Save(2 * idx));
+                self.node(inner);
+                self.prog.push(Inst::Save(2 * idx + 1));
+            }
+            Node::Concat(nodes) => nodes.iter().for_each(|node| self.node(node)),
+            Node::Alt(alts) => {
+                // Split to every alternative but the last, which all jump
+                // to the end once matched
+                let mut jumps = Vec::new();
+                for (i, alt) in alts.iter().enumerate() {
+                    if i + 1 < alts.len() {
+                        let split = self.prog.len();
+                        self.prog.push(Inst::Split(split + 1, 0));
+                        self.node(alt);
+                        jumps.push(self.prog.len());
+                        self.prog.push(Inst::Jmp(0));
+                        self.patch_second(split, self.prog.len());
+                    } else {
+                        self.node(alt);
+                    }
+                }
+                let end = self.prog.len();
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.node(node);
+                }
+                match max {
+                    None => {
+                        let split = self.prog.len();
+                        self.prog.push(Inst::Split(0, 0));
+                        let mark = nullable(node).then(|| {
+                            self.marks += 1;
+                            self.marks - 1
+                        });
+                        if let Some(mark) = mark {
+                            self.prog.push(Inst::Mark(mark));
+                        }
+                        self.node(node);
+                        if let Some(mark) = mark {
+                            self.prog.push(Inst::Progress(mark));
+                        }
+                        self.prog.push(Inst::Jmp(split));
+                        self.set_split(split, split + 1, self.prog.len(), *greedy);
+                    }
+                    Some(max) => {
+                        // Every optional iteration can skip to the end
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.prog.len());
+                            self.prog.push(Inst::Split(0, 0));
+                            self.node(node);
+                        }
+                        let end = self.prog.len();
+                        for split in splits {
+                            self.set_split(split, split + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn patch_second(&mut self, split: usize, target: usize) {
+        if let Inst::Split(_, second) = &mut self.prog[split] {
+            *second = target;
+        }
+    }
+
+    /// Greedy repetitions prefer another iteration, lazy ones prefer to stop.
+    fn set_split(&mut self, split: usize, iterate: usize, stop: usize, greedy: bool) {
+        self.prog[split] = if greedy {
+            Inst::Split(iterate, stop)
+        } else {
+            Inst::Split(stop, iterate)
+        };
+    }
+}
+
+/// Whether a node can match the empty string.
+fn nullable(node: &Node) -> bool {
+    match node {
+        Node::Char(_) | Node::Any | Node::Class(..) => false,
+        Node::LineStart | Node::LineEnd | Node::WordBoundary => true,
+        Node::Group(inner, _) => nullable(inner),
+        Node::Concat(nodes) => nodes.iter().all(nullable),
+        Node::Alt(alts) => alts.iter().any(nullable),
+        Node::Repeat { node, min, .. } => *min == 0 || nullable(node),
+    }
+}
+
+enum Frame {
+    /// A thread to continue with, at an instruction and position.
+    Try(usize, usize),
+    RestoreSlot(usize, Option<usize>),
+    RestoreMark(usize, usize),
+}
+
+enum Outcome {
+    Match,
+    NoMatch,
+    GaveUp,
+}
+
+/// The state of a search, shared by all start positions. States that
+/// failed from one start fail from every other, since only captures and
+/// marks differ, so the visited states are kept across starts.
+struct Search<'a> {
+    prog: &'a [Inst],
+    text: &'a [char],
+    visited: Option<Vec<u64>>,
+    budget: usize,
+    slots: Vec<Option<usize>>,
+    marks: Vec<usize>,
+    stack: Vec<Frame>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, start: usize) -> Outcome {
+        self.slots[0] = Some(start);
+        self.stack.clear();
+        self.stack.push(Frame::Try(0, start));
+        while let Some(frame) = self.stack.pop() {
+            let (mut pc, mut pos) = match frame {
+                Frame::Try(pc, pos) => (pc, pos),
+                Frame::RestoreSlot(slot, old) => {
+                    self.slots[slot] = old;
+                    continue;
+                }
+                Frame::RestoreMark(mark, old) => {
+                    self.marks[mark] = old;
+                    continue;
+                }
+            };
+            loop {
+                match &mut self.visited {
+                    Some(visited) => {
+                        let state = pc * (self.text.len() + 1) + pos;
+                        let (word, bit) = (state / 64, 1 << (state % 64));
+                        if visited[word] & bit != 0 {
+                            break;
+                        }
+                        visited[word] |= bit;
+                    }
+                    None if self.budget == 0 => return Outcome::GaveUp,
+                    None => self.budget -= 1,
+                }
+
+                let text = self.text;
+                match &self.prog[pc] {
+                    Inst::Char(_) | Inst::Any | Inst::Class(..) => {
+                        match text.get(pos) {
+                            Some(&c) if consumes(&self.prog[pc], c) => {
+                                pc += 1;
+                                pos += 1;
+                            }
+                            _ => break,
+                        };
+                    }
+                    Inst::LineStart if pos == 0 || text[pos - 1] == '\n' => pc += 1,
+                    Inst::LineEnd if pos == text.len() || text[pos] == '\n' => pc += 1,
+                    Inst::WordBoundary
+                        if (pos > 0 && is_word(text[pos - 1]))
+                            != (pos < text.len() && is_word(text[pos])) =>
+                    {
+                        pc += 1
+                    }
+                    Inst::LineStart | Inst::LineEnd | Inst::WordBoundary => break,
+                    Inst::Split(first, second) => {
+                        self.stack.push(Frame::Try(*second, pos));
+                        pc = *first;
+                    }
+                    Inst::Jmp(target) => pc = *target,
+                    Inst::Save(slot) => {
+                        self.stack
+                            .push(Frame::RestoreSlot(*slot, self.slots[*slot]));
+                        self.slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    // With the visited states, an empty iteration fails by
+                    // returning to the state it started in
+                    Inst::Mark(_) | Inst::Progress(_) if self.visited.is_some() => pc += 1,
+                    Inst::Mark(mark) => {
+                        self.stack
+                            .push(Frame::RestoreMark(*mark, self.marks[*mark]));
+                        self.marks[*mark] = pos;
+                        pc += 1;
+                    }
+                    Inst::Progress(mark) if self.marks[*mark] == pos => break,
+                    Inst::Progress(_) => pc += 1,
+                    Inst::Match => {
+                        self.slots[1] = Some(pos);
+                        return Outcome::Match;
+                    }
+                }
+            }
+        }
+        Outcome::NoMatch
+    }
+}
+
+fn consumes(inst: &Inst, c: char) -> bool {
+    match inst {
+        Inst::Char(x) => *x == c,
+        Inst::Any => c != '\n',
+        Inst::Class(items, negated) => items.iter().any(|i| i.matches(c)) != *negated,
+        _ => false,
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn error(&self, msg: &str) -> String {
+        let source = self.chars.iter().collect::<String>();
+        format!("{} at offset {} in regex '{}'", msg, self.pos, source)
+    }
+
+    fn alt(&mut self) -> Result<Node, String> {
+        let mut alts = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alts.push(self.concat()?);
+        }
+        Ok(if alts.len() == 1 {
+            alts.pop().unwrap()
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let start = self.pos;
+                self.pos += 1;
+                let bounds = self.bounds();
+                match bounds {
+                    Some(bounds) => {
+                        self.pos -= 1;
+                        bounds
+                    }
+                    None => {
+                        // Not a valid repetition, so a literal '{'
+                        self.pos = start;
+                        return Ok(atom);
+                    }
+                }
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+
+        if matches!(
+            atom,
+            Node::LineStart | Node::LineEnd | Node::WordBoundary | Node::Repeat { .. }
+        ) {
+            return Err(self.error("nothing to repeat"));
+        }
+
+        let greedy = if self.peek() == Some('?') {
+            self.pos += 1;
+            false
+        } else {
+            true
+        };
+
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    /// Parses `n}`, `n,}` or `n,m}` after a `{`.
+    fn bounds(&mut self) -> Option<(usize, Option<usize>)> {
+        let min = self.number()?;
+        let max = if self.peek() == Some(',') {
+            self.pos += 1;
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.number()?)
+            }
+        } else {
+            Some(min)
+        };
+        if self.next() != Some('}') || max.is_some_and(|max| max < min) {
+            return None;
+        }
+        Some((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            None => Err(self.error("unexpected end")),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::LineStart),
+            Some('$') => Ok(Node::LineEnd),
+            Some('(') => {
+                let idx = if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let inner = self.alt()?;
+                if self.next() != Some(')') {
+                    return Err(self.error("unclosed group"));
+                }
+                Ok(Node::Group(Box::new(inner), idx))
+            }
+            Some('[') => self.class(),
+            Some('\\') => match self.next() {
+                Some('b') => Ok(Node::WordBoundary),
+                Some(c) => match class_escape(c) {
+                    Some(item) => Ok(Node::Class(vec![item], false)),
+                    None => Ok(Node::Char(literal_escape(c))),
+                },
+                None => Err(self.error("trailing backslash")),
+            },
+            Some(c @ ('*' | '+' | '?')) => {
+                Err(self.error(&format!("nothing to repeat with '{}'", c)))
+            }
+            Some(c) => Ok(Node::Char(c)),
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = if self.peek() == Some('^') {
+            self.pos += 1;
+            true
+        } else {
+            false
+        };
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                None => return Err(self.error("unclosed character class")),
+                Some(']') if !first => break,
+                Some('\\') => {
+                    let e = self
+                        .next()
+                        .ok_or_else(|| self.error("trailing backslash"))?;
+                    if let Some(item) = class_escape(e) {
+                        items.push(item);
+                        first = false;
+                        continue;
+                    }
+                    literal_escape(e)
+                }
+                Some(c) => c,
+            };
+            first = false;
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let hi = match self.next() {
+                    Some('\\') => literal_escape(
+                        self.next()
+                            .ok_or_else(|| self.error("trailing backslash"))?,
+                    ),
+                    Some(hi) => hi,
+                    None => return Err(self.error("unclosed character class")),
+                };
+                if hi < c {
+                    return Err(self.error("invalid class range"));
+                }
+                items.push(ClassItem::Range(c, hi));
+            } else {
+                items.push(ClassItem::Range(c, c));
+            }
+        }
+
+        Ok(Node::Class(items, negated))
+    }
+}
+
+fn class_escape(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(false)),
+        'D' => Some(ClassItem::Digit(true)),
+        'w' => Some(ClassItem::Word(false)),
+        'W' => Some(ClassItem::Word(true)),
+        's' => Some(ClassItem::Space(false)),
+        'S' => Some(ClassItem::Space(true)),
+        _ => None,
+    }
+}
+
+fn literal_escape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        Regex::new(pattern).unwrap().captures(text)
+    }
+
+    /// The whole match, or `None`.
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        caps(pattern, text).and_then(|c| c[0].clone())
+    }
+
+    fn group(pattern: &str, text: &str) -> Option<String> {
+        caps(pattern, text).and_then(|c| c[1].clone())
+    }
+
+    #[test]
+    fn matches_literals_and_classes() {
+        assert_eq!(find("b.d", "abcde"), Some("bcd".into()));
+        assert_eq!(find("x", "abc"), None);
+        assert_eq!(find("[a-c]+", "xxbcay"), Some("bca".into()));
+        assert_eq!(find("[^a-c]+", "abxyc"), Some("xy".into()));
+        assert_eq!(find("[-a]+", "b-a-c"), Some("-a-".into()));
+        assert_eq!(find("[]a]+", "b]a"), Some("]a".into()));
+        assert_eq!(find(r"\d+", "ab 123 c"), Some("123".into()));
+        assert_eq!(find(r"\D+", "12ab3"), Some("ab".into()));
+        assert_eq!(find(r"\w+", "  foo_1 "), Some("foo_1".into()));
+        assert_eq!(find(r"\s+\S", "a \t b"), Some(" \t b".into()));
+        assert_eq!(find(r"[\d.]+", "v1.25x"), Some("1.25".into()));
+        assert_eq!(find(r"a\.b\nc", "axb a.b\nc"), Some("a.b\nc".into()));
+        assert_eq!(find(".", "\n"), None);
+        assert_eq!(find("a{2}", "a aa"), Some("aa".into()));
+        assert_eq!(find("a{,2}", "a{,2}"), Some("a{,2}".into()));
+    }
+
+    #[test]
+    fn matches_alternations_and_groups() {
+        assert_eq!(find("cat|dog", "hotdog"), Some("dog".into()));
+        // Leftmost first, not longest
+        assert_eq!(find("a|ab", "ab"), Some("a".into()));
+        assert_eq!(group("(a|ab)c", "abc"), Some("ab".into()));
+        assert_eq!(
+            caps(r"(\w+)=(\d+)", "x loss=42"),
+            Some(vec![
+                Some("loss=42".into()),
+                Some("loss".into()),
+                Some("42".into())
+            ])
+        );
+        assert_eq!(group("(?:ab)+(c)", "ababc"), Some("c".into()));
+        // Groups that did not take part in the match capture nothing
+        assert_eq!(
+            caps("(a)|(b)", "b"),
+            Some(vec![Some("b".into()), None, Some("b".into())])
+        );
+        // Repeated groups capture their last iteration
+        assert_eq!(group("(?:(\\d),)+", "1,2,3,"), Some("3".into()));
+        assert_eq!(Regex::new("(a)(?:b)(c)").unwrap().groups(), 2);
+    }
+
+    #[test]
+    fn matches_anchors() {
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("^b.*$", "a\nbc\nd"), Some("bc".into()));
+        assert_eq!(find("a$", "ba\nc"), Some("a".into()));
+        assert_eq!(find(r"\bcat\b", "concat cat"), Some("cat".into()));
+        assert_eq!(find(r"\bcat\b", "concat"), None);
+        assert_eq!(
+            group(r".*loss (\S+)", "x\nstep 1 loss 0.5\n"),
+            Some("0.5".into())
+        );
+    }
+
+    #[test]
+    fn matches_greedy_and_lazy() {
+        assert_eq!(find("a.*b", "aXbYb"), Some("aXbYb".into()));
+        assert_eq!(find("a.*?b", "aXbYb"), Some("aXb".into()));
+        assert_eq!(find("a+?", "aaa"), Some("a".into()));
+        assert_eq!(find("a??b", "ab"), Some("ab".into()));
+        assert_eq!(find("a{2,3}", "aaaa"), Some("aaa".into()));
+        assert_eq!(find("a{2,3}?", "aaaa"), Some("aa".into()));
+        assert_eq!(find("a{2,}", "aaaa"), Some("aaaa".into()));
+        assert_eq!(find("(?:ab){2,}?", "ababab"), Some("abab".into()));
+        assert_eq!(find("x*", "abc"), Some("".into()));
+    }
+
+    #[test]
+    fn empty_iterations_terminate() {
+        assert_eq!(find("(a*)*b", "aab"), Some("aab".into()));
+        assert_eq!(find("(a|)*b", "aab"), Some("aab".into()));
+        assert_eq!(find("(?:a*)+$", "aa"), Some("aa".into()));
+        assert_eq!(find("(^|x)*y", "xxy"), Some("xxy".into()));
+        assert_eq!(find("(a*)*c", "aab"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in [
+            "(a",
+            "a)",
+            "[a",
+            "*a",
+            "a**",
+            "^*",
+            "[z-a]",
+            "a\\",
+            "(a){100000}",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn handles_pathological_input() {
+        // Each iteration of the repetition used to recurse
+        let mut output = "ab".repeat(200_000);
+        output.push_str("\nloss 0.25\n");
+        assert_eq!(group(r"(?:ab|\n)*loss (\S+)", &output), Some("0.25".into()));
+        assert_eq!(group(r"(?:ab|\n)*lost (\S+)", &output), None);
+
+        let long = "a".repeat(100_000);
+        assert_eq!(find("(a|aa)*c", &long), None);
+        assert_eq!(find("(a*)*c", &long), None);
+        assert_eq!(find("(x+x+)+y", &"x".repeat(5_000)), None);
+        assert_eq!(find("a*$", &long).map(|m| m.len()), Some(100_000));
+    }
+
+    #[test]
+    fn gives_up_on_texts_too_long_to_track() {
+        // Too many states to track, so the search is bounded instead
+        let long = "ab".repeat(4_000_000);
+        let regex = Regex::new(r"(?:ab|\n)*loss").unwrap();
+        assert!(regex.prog.len() * (long.len() + 1) > MAX_VISITED);
+        assert_eq!(regex.captures(&long), None);
+        let regex = Regex::new(r"b(a)").unwrap();
+        assert_eq!(
+            regex.captures(&long).map(|c| c[1].clone()),
+            Some(Some("a".into()))
+        );
+        assert!(Regex::new(r"(a*)*b")
+            .unwrap()
+            .is_match(&format!("{}b", "a".repeat(8_000_000))));
+    }
+}
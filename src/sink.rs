@@ -0,0 +1,78 @@
+use std::{
+    io::{BufWriter, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::json::Json;
+
+/// A user command receiving the records, see `--result-sink`.
+///
+/// The command is run once through the shell and gets one JSON record
+/// per line on its stdin, in the order the tasks complete. If it exits
+/// early, the remaining records are dropped with a single warning, while
+/// the run continues. Its stdout goes to the stderr of prun, so that it
+/// stays apart from the records of `--output-on-stdout`.
+pub struct ResultSink {
+    cmd: String,
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+}
+
+impl ResultSink {
+    pub fn open(cmd: &str) -> std::io::Result<ResultSink> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::piped())
+            .stdout(std::io::stderr())
+            .spawn()?;
+        let stdin = child.stdin.take().map(BufWriter::new);
+        Ok(ResultSink {
+            cmd: cmd.to_string(),
+            child,
+            stdin,
+        })
+    }
+
+    pub fn send(&mut self, record: &Json) {
+        let written = match self.stdin.as_mut() {
+            Some(stdin) => writeln!(stdin, "{}", record),
+            None => return,
+        };
+        self.check(written);
+    }
+
+    pub fn flush(&mut self) {
+        let flushed = match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => return,
+        };
+        self.check(flushed);
+    }
+
+    /// Stops feeding the sink after a failed write, i.e. once it exited.
+    fn check(&mut self, result: std::io::Result<()>) {
+        if let Err(e) = result {
+            eprintln!(
+                "\n[PRUN] Warning: the result sink '{}' stopped reading ({}), dropping further records",
+                self.cmd, e
+            );
+            self.stdin = None;
+        }
+    }
+
+    /// Closes the stdin of the sink and waits for it to exit.
+    pub fn close(mut self) -> std::io::Result<()> {
+        self.flush();
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "'{}' exited with {}",
+                self.cmd, status
+            )))
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::json::Json;
+
+/// Collects task begin/end events in the Chrome Trace Event Format, which
+/// can be loaded into `chrome://tracing` or Perfetto. Every worker is
+/// reported as its own thread, so it appears as a separate track.
+pub struct Trace {
+    file: Mutex<Option<File>>,
+    start: Instant,
+    events: Mutex<Vec<Json>>,
+}
+
+impl Trace {
+    /// Creates the trace file upfront, so that an unwritable path is
+    /// reported before the run starts.
+    pub fn create(path: &Path, workers: usize) -> std::io::Result<Trace> {
+        let file = File::create(path)?;
+        let events = (0..workers)
+            .map(|worker| {
+                Json::obj()
+                    .with("name", "thread_name")
+                    .with("ph", "M")
+                    .with("pid", std::process::id() as usize)
+                    .with("tid", worker)
+                    .with(
+                        "args",
+                        Json::obj().with("name", format!("Worker #{}", worker)),
+                    )
+            })
+            .collect();
+
+        Ok(Trace {
+            file: Mutex::new(Some(file)),
+            start: Instant::now(),
+            events: Mutex::new(events),
+        })
+    }
+
+    pub fn begin(&self, worker: usize, name: &str) {
+        self.event(worker, name, "B");
+    }
+
+    pub fn end(&self, worker: usize, name: &str) {
+        self.event(worker, name, "E");
+    }
+
+    fn event(&self, worker: usize, name: &str, ph: &str) {
+        let ts = self.start.elapsed().as_secs_f64() * 1e6;
+        let event = Json::obj()
+            .with("name", name)
+            .with("ph", ph)
+            .with("ts", ts)
+            .with("pid", std::process::id() as usize)
+            .with("tid", worker);
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// Writes all recorded events. Later calls have no effect.
+    pub fn write(&self) -> std::io::Result<()> {
+        let Some(file) = self.file.lock().unwrap().take() else {
+            return Ok(());
+        };
+
+        let events = std::mem::take(&mut *self.events.lock().unwrap());
+        let trace = Json::obj().with("traceEvents", Json::Arr(events));
+
+        let mut file = BufWriter::new(file);
+        writeln!(file, "{}", trace)?;
+        file.flush()
+    }
+}
@@ -0,0 +1,444 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::TaskResult;
+
+/// The state of every task of the run, as shown by the `Tui`. Workers
+/// mark the tasks they start, the writer the tasks that completed.
+pub struct Board {
+    rows: Mutex<Vec<Row>>,
+}
+
+struct Row {
+    name: String,
+    status: Status,
+    started: Option<Instant>,
+    duration: Option<Duration>,
+    metrics: String,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Running,
+    Ok,
+    Skipped,
+    Failed(String),
+}
+
+impl Status {
+    /// Failures first, since they are what one looks for.
+    fn rank(&self) -> u8 {
+        match self {
+            Status::Failed(_) => 0,
+            Status::Running => 1,
+            Status::Pending => 2,
+            Status::Ok | Status::Skipped => 3,
+        }
+    }
+
+    fn text(&self) -> &str {
+        match self {
+            Status::Pending => "pending",
+            Status::Running => "running",
+            Status::Ok => "ok",
+            Status::Skipped => "skipped",
+            Status::Failed(reason) => reason,
+        }
+    }
+}
+
+impl Board {
+    pub fn new<'a>(names: impl Iterator<Item = &'a str>) -> Board {
+        let rows = names
+            .map(|name| Row {
+                name: name.to_string(),
+                status: Status::Pending,
+                started: None,
+                duration: None,
+                metrics: String::new(),
+            })
+            .collect();
+        Board {
+            rows: Mutex::new(rows),
+        }
+    }
+
+    /// Marks a task as running. Names repeat with `--repeat`, so this
+    /// picks the first pending task of the name.
+    pub fn start(&self, name: &str) {
+        let mut rows = self.rows.lock().unwrap();
+        if let Some(row) = rows
+            .iter_mut()
+            .find(|row| row.name == name && row.status == Status::Pending)
+        {
+            row.status = Status::Running;
+            row.started = Some(Instant::now());
+        }
+    }
+
+    /// Marks a pending task as skipped, see `Task::skip_if_exists`.
+    pub fn skip(&self, name: &str) {
+        let mut rows = self.rows.lock().unwrap();
+        if let Some(row) = rows
+            .iter_mut()
+            .find(|row| row.name == name && row.status == Status::Pending)
+        {
+            row.status = Status::Skipped;
+        }
+    }
+
+    pub fn finish(&self, result: &TaskResult) {
+        let mut rows = self.rows.lock().unwrap();
+        // Simulated tasks complete without having been started
+        let idx = [Status::Running, Status::Pending]
+            .iter()
+            .find_map(|status| {
+                rows.iter()
+                    .position(|row| row.name == result.name && row.status == *status)
+            });
+        let Some(row) = idx.map(|idx| &mut rows[idx]) else {
+            return;
+        };
+        row.status = match (result.exit_code, result.signal) {
+            (Some(0), _) => Status::Ok,
+            (Some(code), _) => Status::Failed(format!("exit {}", code)),
+            (None, Some(signal)) => Status::Failed(format!("signal {}", signal)),
+            (None, None) => Status::Failed("failed".to_string()),
+        };
+        row.duration = Some(result.duration);
+        row.metrics = result
+            .metrics
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+}
+
+#[cfg(unix)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Plan,
+    Name,
+    Status,
+    Duration,
+}
+
+#[cfg(unix)]
+impl SortBy {
+    fn next(self) -> SortBy {
+        match self {
+            SortBy::Plan => SortBy::Name,
+            SortBy::Name => SortBy::Status,
+            SortBy::Status => SortBy::Duration,
+            SortBy::Duration => SortBy::Plan,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            SortBy::Plan => "plan",
+            SortBy::Name => "name",
+            SortBy::Status => "status",
+            SortBy::Duration => "duration",
+        }
+    }
+}
+
+/// A full-screen table of the tasks of the run, see `--tui`.
+///
+/// The terminal is switched to its alternate screen and read key by key
+/// while the table is shown. The table is redrawn ten times a second, so
+/// warnings written to the terminal in between are painted over. Keys:
+///
+/// - `↑`/`↓` or `k`/`j` scroll by a line, `PgUp`/`PgDn` by a page
+/// - `s` cycles the sorted column, `r` reverses the order
+/// - `/` edits the filter on names and metrics, `Enter` keeps it and
+///   `Esc` clears it
+/// - `q` closes the table. During the run, prun continues with its
+///   plain output. `Ctrl-C` interrupts prun as usual.
+#[cfg(unix)]
+pub struct Tui {
+    saved: libc::termios,
+    scroll: usize,
+    sort: SortBy,
+    reverse: bool,
+    filter: String,
+    editing: bool,
+    /// Whether failed tasks are shown in red, see `--color`.
+    color: bool,
+}
+
+#[cfg(unix)]
+impl Tui {
+    /// Takes over the terminal, which must be both stdin and stdout.
+    pub fn open(color: bool) -> std::io::Result<Tui> {
+        // SAFETY: termios is plain data, filled in by tcgetattr
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: the pointer is to a valid termios for the duration of the call
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        // Reads return after a tenth of a second without input
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 1;
+        // SAFETY: as above
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = std::io::stdout().flush();
+
+        Ok(Tui {
+            saved,
+            scroll: 0,
+            sort: SortBy::Plan,
+            reverse: false,
+            filter: String::new(),
+            editing: false,
+            color,
+        })
+    }
+
+    /// Shows the board until `q` is pressed. Once `finished` is set, the
+    /// table stays up for browsing the results.
+    pub fn run(mut self, board: &Board, finished: &AtomicBool) {
+        loop {
+            self.draw(board, finished.load(Ordering::Relaxed));
+            let mut buf = [0u8; 16];
+            // SAFETY: reads into a buffer of the given length
+            let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                continue;
+            }
+            for key in keys(&buf[..n as usize]) {
+                if !self.key(key) {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handles the bytes of a key press, returning false to quit.
+    fn key(&mut self, key: &[u8]) -> bool {
+        const PAGE: usize = 10;
+        if key == [3] {
+            self.restore();
+            // Like the terminal would, if it still handled Ctrl-C
+            // SAFETY: signals the process group, which prun is part of
+            unsafe { libc::kill(0, libc::SIGINT) };
+            return false;
+        }
+        if self.editing {
+            match key {
+                b"\r" | b"\n" => self.editing = false,
+                b"\x1b" => {
+                    self.editing = false;
+                    self.filter.clear();
+                }
+                [0x7f] | [0x08] => {
+                    self.filter.pop();
+                }
+                key => {
+                    if let Ok(text) = std::str::from_utf8(key) {
+                        self.filter.extend(text.chars().filter(|c| !c.is_control()));
+                    }
+                }
+            }
+            self.scroll = 0;
+            return true;
+        }
+        match key {
+            b"q" => return false,
+            b"/" => self.editing = true,
+            b"s" => self.sort = self.sort.next(),
+            b"r" => self.reverse = !self.reverse,
+            b"j" | b"\x1b[B" => self.scroll += 1,
+            b"k" | b"\x1b[A" => self.scroll = self.scroll.saturating_sub(1),
+            b"\x1b[6~" => self.scroll += PAGE,
+            b"\x1b[5~" => self.scroll = self.scroll.saturating_sub(PAGE),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, board: &Board, finished: bool) {
+        let (width, height) = terminal_size();
+        let rows = board.rows.lock().unwrap();
+
+        let count = |status: fn(&Status) -> bool| rows.iter().filter(|r| status(&r.status)).count();
+        let done = count(|s| matches!(s, Status::Ok | Status::Skipped | Status::Failed(_)));
+        let running = count(|s| *s == Status::Running);
+        let failed = count(|s| matches!(s, Status::Failed(_)));
+
+        let filter = self.filter.to_lowercase();
+        let mut shown = rows
+            .iter()
+            .filter(|row| {
+                filter.is_empty()
+                    || row.name.to_lowercase().contains(&filter)
+                    || row.metrics.to_lowercase().contains(&filter)
+            })
+            .collect::<Vec<_>>();
+        let elapsed = |row: &Row| {
+            row.duration
+                .or_else(|| row.started.map(|started| started.elapsed()))
+        };
+        match self.sort {
+            SortBy::Plan => {}
+            SortBy::Name => shown.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortBy::Status => shown.sort_by_key(|row| row.status.rank()),
+            SortBy::Duration => shown.sort_by_key(|row| std::cmp::Reverse(elapsed(row))),
+        }
+        if self.reverse {
+            shown.reverse();
+        }
+
+        // Two lines of header, one of footer
+        let page = height.saturating_sub(3).max(1);
+        self.scroll = self.scroll.min(shown.len().saturating_sub(page));
+
+        let state = if finished {
+            "finished".to_string()
+        } else {
+            format!("{} running", running)
+        };
+        let mut lines = vec![format!(
+            "prun: {}/{} done, {}, {} failed | sort: {}{} | filter: {}{}",
+            done,
+            rows.len(),
+            state,
+            failed,
+            self.sort.text(),
+            if self.reverse { " (reversed)" } else { "" },
+            self.filter,
+            if self.editing { "_" } else { "" },
+        )];
+
+        let name_width = rows
+            .iter()
+            .map(|row| row.name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .clamp(4, (width / 2).max(4));
+        lines.push(format!(
+            "\x1b[1m{:<nw$}  {:<10}  {:>10}  METRICS\x1b[0m",
+            "TASK",
+            "STATUS",
+            "DURATION",
+            nw = name_width
+        ));
+        for row in shown.iter().skip(self.scroll).take(page) {
+            let duration = elapsed(row)
+                .map(|d| format!("{:.2}s", d.as_secs_f64()))
+                .unwrap_or_default();
+            let line = format!(
+                "{:<nw$}  {:<10}  {:>10}  {}",
+                truncate(&row.name, name_width),
+                row.status.text(),
+                duration,
+                row.metrics,
+                nw = name_width
+            );
+            let line = truncate(&line, width);
+            lines.push(match row.status {
+                Status::Failed(_) if self.color => format!("\x1b[31m{}\x1b[0m", line),
+                _ => line,
+            });
+        }
+        while lines.len() < height.saturating_sub(1) {
+            lines.push(String::new());
+        }
+        lines.push(format!(
+            "\x1b[7m{}\x1b[0m",
+            truncate(
+                "q quit  / filter  s sort  r reverse  j/k scroll  PgUp/PgDn page",
+                width
+            )
+        ));
+        drop(rows);
+
+        let mut frame = String::from("\x1b[H");
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                frame.push_str("\r\n");
+            }
+            frame.push_str(line);
+            frame.push_str("\x1b[K");
+        }
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(frame.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    fn restore(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        // SAFETY: restores the settings read by `open`
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Splits the bytes of a read into keys, since pasted text or fast
+/// typing arrives at once. A key is an escape sequence like `\x1b[A`
+/// or a single character.
+#[cfg(unix)]
+fn keys(mut bytes: &[u8]) -> Vec<&[u8]> {
+    let mut keys = Vec::new();
+    while !bytes.is_empty() {
+        let len = if bytes.starts_with(b"\x1b[") {
+            bytes[2..]
+                .iter()
+                .position(|b| (0x40..=0x7e).contains(b))
+                .map_or(bytes.len(), |end| end + 3)
+        } else {
+            let len = match bytes[0] {
+                0xf0.. => 4,
+                0xe0.. => 3,
+                0xc0.. => 2,
+                _ => 1,
+            };
+            len.min(bytes.len())
+        };
+        let (key, rest) = bytes.split_at(len);
+        keys.push(key);
+        bytes = rest;
+    }
+    keys
+}
+
+/// Cuts a line to a number of characters, for lines without escapes.
+#[cfg(unix)]
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// The columns and rows of the terminal on stdout, 80x24 if unknown.
+#[cfg(unix)]
+fn terminal_size() -> (usize, usize) {
+    // SAFETY: winsize is plain data, filled in by the ioctl
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ writes a winsize through the pointer
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 && size.ws_row > 0 {
+        (size.ws_col as usize, size.ws_row as usize)
+    } else {
+        (80, 24)
+    }
+}